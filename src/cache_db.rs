@@ -1,6 +1,9 @@
 use super::datatype::*;
+use super::{log_debug, log_info};
 use chrono::Timelike;
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior, params, params_from_iter};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,6 +13,9 @@ pub enum CacheError {
 
     #[error("Sqlite Error: {0}")]
     Sqlite(rusqlite::Error),
+
+    #[error("The cache has schema version {found}, newer than the supported version {supported}")]
+    NewerSchema { found: i64, supported: i64 },
 }
 
 impl From<serde_json::Error> for CacheError {
@@ -26,62 +32,145 @@ impl From<rusqlite::Error> for CacheError {
 
 pub type CacheResult<T> = Result<T, CacheError>;
 
+// How many lines of the last parsing run were found in the cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunStats {
+    pub hit_count: usize,
+    pub line_count: usize,
+}
+
+impl RunStats {
+    pub fn hit_rate(&self) -> f64 {
+        if self.line_count == 0 {
+            0.0
+        } else {
+            self.hit_count as f64 / self.line_count as f64
+        }
+    }
+}
+
+// The schema version kept in the user_version pragma of the database.
+pub const SCHEMA_VERSION: i64 = 2;
+
+// The max number of hash values looked up in one query, below the sqlite variable limit.
+const QUERY_CHUNK_SIZE: usize = 500;
+
+// How long to wait for another run holding the write lock before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// MIGRATION_LIST[n] upgrades the schema from version n to n + 1, the databases created
+// before the versioning report 0 with the version 1 layout.
+const MIGRATION_LIST: &[&str] = &[
+    // 1: the original layout.
+    "CREATE TABLE metadata (
+    id TEXT PRIMARY KEY,
+    serialized_data TEXT NOT NULL,
+    name TEXT NOT NULL,
+    b_finished BOOL NOT NULL,
+    episode INTEGER,
+    time_at_episode INTEGER,
+    season INTERGER,
+    logged_time INTEGER,
+    note TEXT,
+    raw_line TEXT,
+    reg_pattern_matched TEXT,
+    finished_reg_pattern_matched TEXT);",
+    // 2: fix the season type, replace b_finished with the watch status and keep the stats of
+    // the last run. The version 1 rows are dropped rather than copied, their serialized data
+    // predates the watch status and their keys predate the pattern pool hash, so they can
+    // never be looked up again.
+    "DROP TABLE metadata;
+    CREATE TABLE metadata (
+    id TEXT PRIMARY KEY,
+    serialized_data TEXT NOT NULL,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL,
+    episode INTEGER,
+    time_at_episode INTEGER,
+    season INTEGER,
+    logged_time INTEGER,
+    note TEXT,
+    raw_line TEXT,
+    reg_pattern_matched TEXT,
+    status_reg_pattern_matched TEXT);
+    CREATE TABLE IF NOT EXISTS run_stats (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    hit_count INTEGER NOT NULL,
+    line_count INTEGER NOT NULL);",
+];
+
 pub struct Cache {
     conn: Connection,
+    // The schema version of the database before it was upgraded.
+    found_schema_version: i64,
 }
 
 impl Cache {
     pub fn new(db_path: &str) -> CacheResult<Self> {
-        let conn = Connection::open(db_path)?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS metadata (
-            id TEXT PRIMARY KEY,
-            serialized_data TEXT NOT NULL,
-            name TEXT NOT NULL,
-            b_finished BOOL NOT NULL,
-            episode INTEGER,
-            time_at_episode INTEGER,
-            season INTERGER,
-            logged_time INTEGER,
-            note TEXT,
-            raw_line TEXT,
-            reg_pattern_matched TEXT,
-            finished_reg_pattern_matched TEXT)",
-            [],
-        )?;
-        Ok(Cache { conn })
+        let mut conn = Connection::open(db_path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        let found_schema_version = schema_version(&conn)?;
+        if found_schema_version > SCHEMA_VERSION {
+            return Err(CacheError::NewerSchema {
+                found: found_schema_version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        // The readers and the writer of concurrent runs do not block each other in WAL mode.
+        let _journal_mode: String =
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        if found_schema_version < SCHEMA_VERSION {
+            migrate(&mut conn)?;
+        }
+        Ok(Cache {
+            conn,
+            found_schema_version,
+        })
     }
 
-    pub fn query_cache(&self, hash_value: u64) -> Option<Metadata> {
-        if let Ok(mut stmt) = self
-            .conn
-            .prepare("SELECT serialized_data FROM metadata WHERE id = ?1")
-        {
-            return stmt
-                .query_row(params![hash_value.to_string()], |row| {
-                    let serialized_data: String = row.get(0)?;
-                    let metadata: Metadata =
-                        serde_json::from_str(&serialized_data).map_err(|e| {
-                            rusqlite::Error::FromSqlConversionFailure(
-                                0,
-                                rusqlite::types::Type::Text,
-                                Box::new(e),
-                            )
-                        })?;
-                    Ok(metadata)
-                })
-                .ok();
+    pub fn found_schema_version(&self) -> i64 {
+        self.found_schema_version
+    }
+
+    // Look up all the hash values in a few queries, the ones not cached are missing from the
+    // map. A row which cannot be deserialized is skipped, so its line is parsed again.
+    pub fn query_cache_map(&self, hash_value_list: &[u64]) -> CacheResult<HashMap<u64, Metadata>> {
+        let mut metadata_map = HashMap::with_capacity(hash_value_list.len());
+        for chunk in hash_value_list.chunks(QUERY_CHUNK_SIZE) {
+            let mut stmt = self.conn.prepare_cached(&format!(
+                "SELECT id, serialized_data FROM metadata WHERE id IN ({})",
+                vec!["?"; chunk.len()].join(", ")
+            ))?;
+            let id_list: Vec<String> = chunk.iter().map(|id| id.to_string()).collect();
+            let mut rows = stmt.query(params_from_iter(id_list.iter()))?;
+            while let Some(row) = rows.next()? {
+                let id: String = row.get(0)?;
+                let serialized_data: String = row.get(1)?;
+                match serde_json::from_str::<Metadata>(&serialized_data) {
+                    Ok(metadata) => {
+                        metadata_map.insert(metadata.id, metadata);
+                    }
+                    Err(e) => log_debug!("Skipping the cached row {}: {}", id, e),
+                }
+            }
         }
-        None
+        Ok(metadata_map)
     }
 
-    pub fn add_cache(&self, metadata: &Metadata) -> CacheResult<()> {
-        let mut stmt = self.conn.prepare(
-            "INSERT INTO metadata (
+    // Insert or update all the entries in one transaction, return the number of written rows.
+    pub fn add_cache_list<'a>(
+        &self,
+        metadata_list: impl IntoIterator<Item = &'a Metadata>,
+    ) -> CacheResult<usize> {
+        let transaction = self.conn.unchecked_transaction()?;
+        let mut row_count = 0;
+        {
+            let mut stmt = transaction.prepare_cached(
+                "INSERT INTO metadata (
             id,
             serialized_data,
             name,
-            b_finished,
+            status,
             episode,
             time_at_episode,
             season,
@@ -89,25 +178,363 @@ impl Cache {
             note,
             raw_line,
             reg_pattern_matched,
-            finished_reg_pattern_matched) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            status_reg_pattern_matched) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            ON CONFLICT(id) DO UPDATE SET
+            serialized_data = excluded.serialized_data,
+            name = excluded.name,
+            status = excluded.status,
+            episode = excluded.episode,
+            time_at_episode = excluded.time_at_episode,
+            season = excluded.season,
+            logged_time = excluded.logged_time,
+            note = excluded.note,
+            raw_line = excluded.raw_line,
+            reg_pattern_matched = excluded.reg_pattern_matched,
+            status_reg_pattern_matched = excluded.status_reg_pattern_matched",
+            )?;
+            for metadata in metadata_list {
+                let serialized_data = serde_json::to_string(metadata)?;
+                row_count += stmt.execute(params![
+                    metadata.id.to_string(),
+                    serialized_data,
+                    metadata.name,
+                    metadata.status.to_string(),
+                    metadata.episode,
+                    metadata
+                        .time_at_episode
+                        .map(|t| t.num_seconds_from_midnight()),
+                    metadata.season,
+                    metadata.logged_time.map(|t| t.and_utc().timestamp()),
+                    metadata.note,
+                    metadata.raw_line,
+                    metadata.reg_pattern_matched,
+                    metadata.status_reg_pattern_matched,
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(row_count)
+    }
+
+    pub fn row_count(&self) -> CacheResult<usize> {
+        let row_count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM metadata", [], |row| row.get(0))?;
+        Ok(row_count as usize)
+    }
+
+    pub fn record_run(&self, run_stats: &RunStats) -> CacheResult<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO run_stats (id, hit_count, line_count) VALUES (0, ?1, ?2)",
+            params![run_stats.hit_count as i64, run_stats.line_count as i64],
         )?;
-        let serialized_data = serde_json::to_string(&metadata)?;
-        stmt.execute(params![
-            metadata.id.to_string(),
-            serialized_data,
-            metadata.name,
-            metadata.b_finished,
-            metadata.episode,
-            metadata
-                .time_at_episode
-                .map(|t| t.num_seconds_from_midnight()),
-            metadata.season,
-            metadata.logged_time.map(|t| t.and_utc().timestamp()),
-            metadata.note,
-            metadata.raw_line,
-            metadata.reg_pattern_matched,
-            metadata.finished_reg_pattern_matched,
-        ])?;
         Ok(())
     }
+
+    pub fn last_run(&self) -> CacheResult<Option<RunStats>> {
+        let run_stats = self
+            .conn
+            .query_row(
+                "SELECT hit_count, line_count FROM run_stats WHERE id = 0",
+                [],
+                |row| {
+                    let hit_count: i64 = row.get(0)?;
+                    let line_count: i64 = row.get(1)?;
+                    Ok(RunStats {
+                        hit_count: hit_count as usize,
+                        line_count: line_count as usize,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(run_stats)
+    }
+
+    // Delete the rows whose id is not in the kept set, return the number of deleted rows.
+    pub fn prune(&self, kept_id_set: &HashSet<u64>) -> CacheResult<usize> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "CREATE TEMP TABLE IF NOT EXISTS kept_id (id TEXT PRIMARY KEY)",
+            [],
+        )?;
+        transaction.execute("DELETE FROM kept_id", [])?;
+        {
+            let mut stmt = transaction.prepare("INSERT OR IGNORE INTO kept_id (id) VALUES (?1)")?;
+            for id in kept_id_set {
+                stmt.execute(params![id.to_string()])?;
+            }
+        }
+        let deleted_count = transaction.execute(
+            "DELETE FROM metadata WHERE id NOT IN (SELECT id FROM kept_id)",
+            [],
+        )?;
+        transaction.execute("DROP TABLE kept_id", [])?;
+        transaction.commit()?;
+        Ok(deleted_count)
+    }
+
+    // Delete all the rows and the stats of the last run, return the number of deleted rows.
+    pub fn clear(&self) -> CacheResult<usize> {
+        let deleted_count = self.conn.execute("DELETE FROM metadata", [])?;
+        self.conn.execute("DELETE FROM run_stats", [])?;
+        Ok(deleted_count)
+    }
+
+    // Give the space of the deleted rows back to the file system.
+    pub fn vacuum(&self) -> CacheResult<()> {
+        self.conn.execute("VACUUM", [])?;
+        Ok(())
+    }
+}
+
+fn schema_version(conn: &Connection) -> CacheResult<i64> {
+    let user_version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if user_version > 0 {
+        return Ok(user_version);
+    }
+    let b_metadata_table_found: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata')",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(if b_metadata_table_found { 1 } else { 0 })
+}
+
+// Run the pending migrations in one transaction, so a failed one leaves the database
+// untouched. The write lock is taken up front and the version is read again under it, since
+// another run opening the same cache may have upgraded it in the meantime.
+fn migrate(conn: &mut Connection) -> CacheResult<()> {
+    let transaction = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let from_version = schema_version(&transaction)?;
+    if from_version > SCHEMA_VERSION {
+        return Err(CacheError::NewerSchema {
+            found: from_version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    if from_version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    log_info!(
+        "Upgrading the cache from schema version {} to {}",
+        from_version,
+        SCHEMA_VERSION
+    );
+    for migration in MIGRATION_LIST.iter().skip(from_version as usize) {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod cache_db_tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::{env, fs, process};
+
+    fn query(cache: &Cache, id: u64) -> Option<Metadata> {
+        cache.query_cache_map(&[id]).unwrap().remove(&id)
+    }
+
+    // Remove the database file with its WAL files, the connections to it must be closed.
+    fn remove_db(db_path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", db_path.display(), suffix));
+        }
+    }
+
+    // A database file prepared by the given statements, it is removed before each test.
+    fn prepared_db_path(name: &str, sql: &str) -> PathBuf {
+        let db_path =
+            env::temp_dir().join(format!("watching_record_{}_{}.db", name, process::id()));
+        remove_db(&db_path);
+        Connection::open(&db_path)
+            .unwrap()
+            .execute_batch(sql)
+            .unwrap();
+        db_path
+    }
+
+    fn metadata(id: u64, name: &str) -> Metadata {
+        Metadata {
+            id,
+            name: name.to_string(),
+            raw_line: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_prune_keeps_referenced_rows() {
+        let cache = Cache::new(":memory:").unwrap();
+        let metadata_list = [metadata(1, "A"), metadata(2, "B"), metadata(3, "C")];
+        assert_eq!(cache.add_cache_list(&metadata_list).unwrap(), 3);
+        assert_eq!(cache.row_count().unwrap(), 3);
+
+        let deleted_count = cache.prune(&HashSet::from([1, 3])).unwrap();
+        assert_eq!(deleted_count, 1);
+        assert_eq!(cache.row_count().unwrap(), 2);
+        assert!(query(&cache, 2).is_none());
+        assert_eq!(query(&cache, 3).unwrap().name, "C");
+    }
+
+    #[test]
+    fn test_clear_drops_rows_and_run_stats() {
+        let cache = Cache::new(":memory:").unwrap();
+        assert_eq!(cache.last_run().unwrap(), None);
+        cache.add_cache_list([&metadata(1, "A")]).unwrap();
+        let run_stats = RunStats {
+            hit_count: 3,
+            line_count: 4,
+        };
+        cache.record_run(&run_stats).unwrap();
+        assert_eq!(cache.last_run().unwrap(), Some(run_stats));
+        assert_eq!(run_stats.hit_rate(), 0.75);
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(cache.row_count().unwrap(), 0);
+        assert_eq!(cache.last_run().unwrap(), None);
+        cache.vacuum().unwrap();
+    }
+
+    #[test]
+    fn test_new_database_has_current_schema() {
+        let cache = Cache::new(":memory:").unwrap();
+        assert_eq!(cache.found_schema_version(), 0);
+        assert_eq!(schema_version(&cache.conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_unversioned_database_drops_old_rows() {
+        let db_path = prepared_db_path(
+            "unversioned",
+            &format!(
+                r#"{}
+                INSERT INTO metadata (id, serialized_data, name, b_finished, season)
+                VALUES ('1', '{{"index":0,"id":1,"name":"A","b_finished":true,"episode":null,"time_at_episode":null,"season":2,"logged_time":null,"note":null,"raw_line":"A 看完","reg_pattern_matched":"","finished_reg_pattern_matched":"看完"}}', 'A', 1, '2');"#,
+                MIGRATION_LIST[0]
+            ),
+        );
+
+        let cache = Cache::new(&db_path.to_string_lossy()).unwrap();
+        assert_eq!(cache.found_schema_version(), 1);
+        assert_eq!(cache.row_count().unwrap(), 0);
+        assert!(query(&cache, 1).is_none());
+        let season_type: String = cache
+            .conn
+            .query_row(
+                "SELECT type FROM pragma_table_info('metadata') WHERE name = 'season'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(season_type, "INTEGER");
+        drop(cache);
+
+        let cache = Cache::new(&db_path.to_string_lossy()).unwrap();
+        assert_eq!(cache.found_schema_version(), SCHEMA_VERSION);
+        drop(cache);
+        remove_db(&db_path);
+    }
+
+    #[test]
+    fn test_concurrent_first_opens_keep_rows() {
+        for round in 0..10 {
+            let db_path = prepared_db_path(&format!("first_open_{}", round), "");
+            let thread_list: Vec<_> = (0..4)
+                .map(|id| {
+                    let db_path_text = db_path.to_string_lossy().to_string();
+                    std::thread::spawn(move || {
+                        let cache = Cache::new(&db_path_text).unwrap();
+                        cache
+                            .add_cache_list([&metadata(id, &id.to_string())])
+                            .unwrap();
+                    })
+                })
+                .collect();
+            for thread in thread_list {
+                thread.join().unwrap();
+            }
+            let cache = Cache::new(&db_path.to_string_lossy()).unwrap();
+            assert_eq!(cache.row_count().unwrap(), 4);
+            drop(cache);
+            remove_db(&db_path);
+        }
+    }
+
+    #[test]
+    fn test_unknown_unversioned_layout_is_rebuilt() {
+        let db_path = prepared_db_path(
+            "unknown_layout",
+            "CREATE TABLE metadata (id TEXT PRIMARY KEY, data TEXT);
+            INSERT INTO metadata VALUES ('1', 'A');",
+        );
+
+        let cache = Cache::new(&db_path.to_string_lossy()).unwrap();
+        assert_eq!(cache.found_schema_version(), 1);
+        assert_eq!(cache.row_count().unwrap(), 0);
+        cache.add_cache_list([&metadata(1, "A")]).unwrap();
+        assert_eq!(query(&cache, 1).unwrap().name, "A");
+        drop(cache);
+        remove_db(&db_path);
+    }
+
+    #[test]
+    fn test_newer_schema_is_rejected() {
+        let db_path = prepared_db_path("newer", "PRAGMA user_version = 99;");
+
+        match Cache::new(&db_path.to_string_lossy()) {
+            Err(CacheError::NewerSchema { found, supported }) => {
+                assert_eq!((found, supported), (99, SCHEMA_VERSION));
+            }
+            _ => panic!("The newer schema should be rejected"),
+        }
+        let journal_mode: String = Connection::open(&db_path)
+            .unwrap()
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "delete");
+        remove_db(&db_path);
+    }
+
+    #[test]
+    fn test_bulk_lookup_across_chunks() {
+        let cache = Cache::new(":memory:").unwrap();
+        let metadata_list: Vec<Metadata> = (0..QUERY_CHUNK_SIZE as u64 + 10)
+            .step_by(2)
+            .map(|id| metadata(id, &id.to_string()))
+            .collect();
+        cache.add_cache_list(&metadata_list).unwrap();
+
+        let hash_value_list: Vec<u64> = (0..QUERY_CHUNK_SIZE as u64 + 10).collect();
+        let metadata_map = cache.query_cache_map(&hash_value_list).unwrap();
+        assert_eq!(metadata_map.len(), metadata_list.len());
+        assert_eq!(metadata_map[&(QUERY_CHUNK_SIZE as u64 + 8)].name, "508");
+        assert!(!metadata_map.contains_key(&1));
+    }
+
+    #[test]
+    fn test_concurrent_connections_use_wal() {
+        let db_path = prepared_db_path("wal", "");
+        let db_path_text = db_path.to_string_lossy();
+        let writer = Cache::new(&db_path_text).unwrap();
+        let reader = Cache::new(&db_path_text).unwrap();
+        let journal_mode: String = reader
+            .conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        // The reader is not blocked by the open write transaction.
+        let transaction = writer.conn.unchecked_transaction().unwrap();
+        transaction.execute("DELETE FROM run_stats", []).unwrap();
+        assert_eq!(reader.row_count().unwrap(), 0);
+        transaction.commit().unwrap();
+        writer.add_cache_list([&metadata(1, "A")]).unwrap();
+        assert_eq!(query(&reader, 1).unwrap().name, "A");
+        drop((writer, reader));
+        remove_db(&db_path);
+    }
 }
@@ -0,0 +1,354 @@
+use super::datatype::{EpisodeKind, WatchStatus};
+use super::notation::{BuiltinNotation, RatingFormat};
+use super::pattern::{CompiledPatternList, METADATA_GROUP_LIST, PatternPool, PatternResult};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    pub reg_pattern_list: Vec<String>,
+    pub finished_reg_pattern_list: Vec<String>,
+    // The entries matching these patterns set the watching to dropped, on hold or plan to watch.
+    #[serde(default)]
+    pub dropped_reg_pattern_list: Vec<String>,
+    #[serde(default)]
+    pub on_hold_reg_pattern_list: Vec<String>,
+    #[serde(default)]
+    pub plan_to_watch_reg_pattern_list: Vec<String>,
+    // The built-in notations appended after reg_pattern_list.
+    #[serde(default)]
+    pub builtin_notation_list: Vec<BuiltinNotation>,
+    pub max_thread_num: Option<usize>,
+    pub min_task_num_per_thread: Option<usize>,
+    // The lines matching these patterns set the date for the following entries.
+    #[serde(default)]
+    pub date_header_pattern_list: Vec<String>,
+    // The chrono formats for logged_time, tried in order before the built-in ones.
+    #[serde(default)]
+    pub logged_time_format_list: Vec<String>,
+    #[serde(default)]
+    pub rating_format: RatingFormat,
+    // The unfinished watching untouched for more days is stale, or abandoned.
+    pub stale_days: Option<u32>,
+    pub abandoned_days: Option<u32>,
+    // The line appended by `stale --drop`, such as `{name}第{season}季 弃 {date}`.
+    pub dropped_entry_template: Option<String>,
+    #[serde(default)]
+    pub examples: Vec<PatternExample>,
+}
+
+// A sample line with its expected parsing result, only the given fields are checked.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatternExample {
+    pub line: String,
+    pub name: Option<String>,
+    pub season: Option<u16>,
+    pub part: Option<u16>,
+    pub episode: Option<u16>,
+    pub episode_end: Option<u16>,
+    pub kind: Option<EpisodeKind>,
+    pub time_at_episode: Option<String>,
+    pub logged_time: Option<String>,
+    pub rating: Option<f32>,
+    pub status: Option<WatchStatus>,
+    pub finished: Option<bool>,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Cannot read the config file {path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Cannot parse the config file {path}: {source}")]
+    Yaml {
+        path: String,
+        #[source]
+        source: serde_yaml::Error,
+    },
+}
+
+pub type ConfigResult<T> = Result<T, ConfigError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[derive(Debug)]
+pub struct ConfigProblem {
+    pub severity: Severity,
+    pub list: &'static str,
+    pub index: usize,
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        };
+        write!(
+            f,
+            "{}: {}[{}]: {}",
+            severity, self.list, self.index, self.message
+        )
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> ConfigResult<Self> {
+        let path_string = path.display().to_string();
+        let config_yaml_data = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path_string.clone(),
+            source,
+        })?;
+        serde_yaml::from_str(&config_yaml_data).map_err(|source| ConfigError::Yaml {
+            path: path_string,
+            source,
+        })
+    }
+
+    pub fn build_pattern_pool(&self) -> PatternResult<PatternPool> {
+        let reg_pattern_list = self
+            .reg_pattern_list
+            .iter()
+            .cloned()
+            .chain(
+                self.builtin_notation_list
+                    .iter()
+                    .map(|notation| notation.pattern()),
+            )
+            .collect();
+        Ok(
+            PatternPool::new(reg_pattern_list, self.finished_reg_pattern_list.clone())?
+                .with_status_reg_pattern_list(
+                    self.dropped_reg_pattern_list.clone(),
+                    self.on_hold_reg_pattern_list.clone(),
+                    self.plan_to_watch_reg_pattern_list.clone(),
+                )?
+                .with_date_header_pattern_list(self.date_header_pattern_list.clone())?
+                .with_logged_time_format_list(self.logged_time_format_list.clone())
+                .with_rating_format(self.rating_format),
+        )
+    }
+
+    // Validate all the patterns without parsing anything. The sample lines are optional, when
+    // given, they are used to find the patterns which never win against the earlier ones.
+    pub fn check(&self, sample_lines: &[String]) -> Vec<ConfigProblem> {
+        let mut problem_list = Vec::new();
+
+        let mut b_reg_pattern_list_valid = true;
+        for (index, pattern) in self.reg_pattern_list.iter().enumerate() {
+            let problem = |severity, message| ConfigProblem {
+                severity,
+                list: "reg_pattern_list",
+                index,
+                message,
+            };
+            let re = match Regex::new(pattern) {
+                Ok(re) => re,
+                Err(e) => {
+                    b_reg_pattern_list_valid = false;
+                    problem_list.push(problem(Severity::Error, format!("invalid regex: {}", e)));
+                    continue;
+                }
+            };
+            let group_name_list: Vec<&str> = re.capture_names().flatten().collect();
+            if !group_name_list.contains(&"name") {
+                problem_list.push(problem(
+                    Severity::Error,
+                    String::from("the pattern has no `name` group"),
+                ));
+            }
+            for group_name in group_name_list {
+                if !METADATA_GROUP_LIST.contains(&group_name) {
+                    problem_list.push(problem(
+                        Severity::Info,
+                        format!(
+                            "the group `{}` is kept as an extra field, the known groups are: {}",
+                            group_name,
+                            METADATA_GROUP_LIST.join(", ")
+                        ),
+                    ));
+                }
+            }
+            if let Some(earlier_index) = self.reg_pattern_list[..index]
+                .iter()
+                .position(|earlier_pattern| earlier_pattern == pattern)
+            {
+                problem_list.push(problem(
+                    Severity::Warning,
+                    format!(
+                        "the pattern is shadowed by the identical reg_pattern_list[{}]",
+                        earlier_index
+                    ),
+                ));
+            }
+        }
+
+        for (list, pattern_list) in [
+            ("finished_reg_pattern_list", &self.finished_reg_pattern_list),
+            ("dropped_reg_pattern_list", &self.dropped_reg_pattern_list),
+            ("on_hold_reg_pattern_list", &self.on_hold_reg_pattern_list),
+            (
+                "plan_to_watch_reg_pattern_list",
+                &self.plan_to_watch_reg_pattern_list,
+            ),
+            ("date_header_pattern_list", &self.date_header_pattern_list),
+        ] {
+            for (index, pattern) in pattern_list.iter().enumerate() {
+                if let Err(e) = Regex::new(pattern) {
+                    problem_list.push(ConfigProblem {
+                        severity: Severity::Error,
+                        list,
+                        index,
+                        message: format!("invalid regex: {}", e),
+                    });
+                }
+            }
+        }
+
+        if b_reg_pattern_list_valid && !sample_lines.is_empty() {
+            problem_list.extend(self.check_shadowed_by_samples(sample_lines));
+        }
+
+        problem_list
+    }
+
+    // A pattern is shadowed if it matches some sample lines, but an earlier pattern always wins.
+    fn check_shadowed_by_samples(&self, sample_lines: &[String]) -> Vec<ConfigProblem> {
+        let Ok(compiled_pattern_list) =
+            CompiledPatternList::new("reg_pattern_list", self.reg_pattern_list.clone())
+        else {
+            return vec![];
+        };
+
+        // For each pattern, count the lines won by it and the earlier patterns winning against it.
+        let mut won_count_list = vec![0usize; self.reg_pattern_list.len()];
+        let mut shadowed_by_list =
+            vec![HashMap::<usize, usize>::new(); self.reg_pattern_list.len()];
+        for line in sample_lines {
+            let match_index_list = compiled_pattern_list.match_index_list(line);
+            let Some(&winner_index) = match_index_list.first() else {
+                continue;
+            };
+            won_count_list[winner_index] += 1;
+            for &index in &match_index_list[1..] {
+                *shadowed_by_list[index].entry(winner_index).or_default() += 1;
+            }
+        }
+
+        let mut problem_list = Vec::new();
+        for (index, shadowed_by) in shadowed_by_list.into_iter().enumerate() {
+            if won_count_list[index] > 0 {
+                continue;
+            }
+            let Some((&winner_index, _)) = shadowed_by
+                .iter()
+                .max_by_key(|(winner_index, count)| (**count, std::cmp::Reverse(**winner_index)))
+            else {
+                continue;
+            };
+            let matched_count: usize = shadowed_by.values().sum();
+            problem_list.push(ConfigProblem {
+                severity: Severity::Warning,
+                list: "reg_pattern_list",
+                index,
+                message: format!(
+                    "the pattern matches {} sample line(s) but never wins, it is shadowed by reg_pattern_list[{}]",
+                    matched_count, winner_index
+                ),
+            });
+        }
+        problem_list
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    fn config(reg_pattern_list: &[&str], finished_reg_pattern_list: &[&str]) -> Config {
+        Config {
+            reg_pattern_list: reg_pattern_list.iter().map(|s| s.to_string()).collect(),
+            finished_reg_pattern_list: finished_reg_pattern_list
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            dropped_reg_pattern_list: vec![],
+            on_hold_reg_pattern_list: vec![],
+            plan_to_watch_reg_pattern_list: vec![],
+            builtin_notation_list: vec![],
+            max_thread_num: None,
+            min_task_num_per_thread: None,
+            date_header_pattern_list: vec![],
+            logged_time_format_list: vec![],
+            rating_format: RatingFormat::default(),
+            stale_days: None,
+            abandoned_days: None,
+            dropped_entry_template: None,
+            examples: vec![],
+        }
+    }
+
+    #[test]
+    fn test_check_reports_index() {
+        let config = config(
+            &[
+                r"(?<name>.+)\s看完$",
+                r"(?<name>.+",
+                r"(?<title>.+)\s第(?<episode>\d+)集$",
+                r"(?<name>.+)\s看完$",
+            ],
+            &[r"\s看完$", r"[bad"],
+        );
+
+        let problem_list: Vec<(Severity, &str, usize)> = config
+            .check(&[])
+            .iter()
+            .map(|p| (p.severity, p.list, p.index))
+            .collect();
+        assert_eq!(
+            problem_list,
+            vec![
+                (Severity::Error, "reg_pattern_list", 1),
+                (Severity::Error, "reg_pattern_list", 2),
+                (Severity::Info, "reg_pattern_list", 2),
+                (Severity::Warning, "reg_pattern_list", 3),
+                (Severity::Error, "finished_reg_pattern_list", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_shadowed_by_samples() {
+        let config = config(
+            &[
+                r"(?<name>.+)\s(?<note>.+)$",
+                r"(?<name>.+)\s看完$",
+                r"(?<name>\S+)$",
+            ],
+            &[],
+        );
+
+        let sample_lines = ["ABC 看完", "DEF"].map(String::from);
+        let problem_list = config.check(&sample_lines);
+        assert_eq!(problem_list.len(), 1);
+        assert_eq!(problem_list[0].severity, Severity::Warning);
+        assert_eq!(problem_list[0].index, 1);
+        assert!(problem_list[0].message.contains("reg_pattern_list[0]"));
+    }
+}
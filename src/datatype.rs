@@ -1,19 +1,250 @@
-use chrono::{NaiveDateTime, NaiveTime};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
 
-#[derive(Clone, Serialize, Deserialize)]
+// The kind of the watched episode, all but the regular ones are counted as specials.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EpisodeKind {
+    #[default]
+    Regular,
+    Special,
+    Ova,
+    Movie,
+    Recap,
+}
+
+impl Display for EpisodeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            EpisodeKind::Regular => "episode",
+            EpisodeKind::Special => "SP",
+            EpisodeKind::Ova => "OVA",
+            EpisodeKind::Movie => "movie",
+            EpisodeKind::Recap => "recap",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+// The status of a watching, set by the status pattern lists matching an entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchStatus {
+    #[default]
+    Watching,
+    Finished,
+    Dropped,
+    OnHold,
+    PlanToWatch,
+}
+
+impl Display for WatchStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            WatchStatus::Watching => "watching",
+            WatchStatus::Finished => "finished",
+            WatchStatus::Dropped => "dropped",
+            WatchStatus::OnHold => "on hold",
+            WatchStatus::PlanToWatch => "plan to watch",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+// Where an entry comes from, it is not part of the cached data, so a cached entry always gets
+// the position of the line it is read from.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Provenance {
+    pub source_path: Option<Arc<Path>>,
+    // 1-based line number in the watching log.
+    pub line_number: usize,
+}
+
+impl Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source_path {
+            Some(source_path) => write!(f, "{}:{}", source_path.display(), self.line_number),
+            None => write!(f, "line {}", self.line_number),
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Metadata {
-    #[serde[skip]]
-    pub index: u32,
+    #[serde(skip)]
+    pub provenance: Provenance,
+    // The hash of the line content and the pattern pool, it is the cache key, so the identical
+    // lines share it.
     pub id: u64,
+    // How many identical lines are before this one, 0 for the first one. The entry is identified
+    // by the id and the occurrence together.
+    #[serde(skip)]
+    pub occurrence: u32,
     pub name: String,
-    pub b_finished: bool,
+    #[serde(default)]
+    pub status: WatchStatus,
     pub episode: Option<u16>,
+    // The last episode of a range, such as `第3-5集`, the range starts from `episode`.
+    #[serde(default)]
+    pub episode_end: Option<u16>,
+    // The decimal episode such as `12.5`, episode is None for it.
+    #[serde(default)]
+    pub episode_decimal: Option<f32>,
+    #[serde(default)]
+    pub kind: EpisodeKind,
     pub time_at_episode: Option<NaiveTime>,
     pub season: Option<u16>,
+    // The part of a split season, such as `第二季下` or `Season 2 Part 2`.
+    #[serde(default)]
+    pub part: Option<u16>,
     pub logged_time: Option<NaiveDateTime>,
+    // The captured text of logged_time, kept for resolving the relative ones.
+    #[serde(default)]
+    pub logged_time_text: Option<String>,
+    // The logged_time is inferred from the previous entry or the file instead of being logged.
+    #[serde(default)]
+    pub b_logged_time_inferred: bool,
     pub note: Option<String>,
+    // The rating normalized to the 0-10 scale, such as 8.0 for `4/5` or `★★★★☆`.
+    #[serde(default)]
+    pub rating: Option<f32>,
+    // The captures of the named groups other than the known ones, such as `platform`.
+    #[serde(default)]
+    pub extra_field_map: BTreeMap<String, String>,
     pub raw_line: String,
     pub reg_pattern_matched: String,
-    pub finished_reg_pattern_matched: Option<String>,
+    // The status pattern which sets the status, None for watching.
+    #[serde(default)]
+    pub status_reg_pattern_matched: Option<String>,
+}
+
+impl Metadata {
+    // All the episodes watched in this entry.
+    pub fn episode_range(&self) -> Option<RangeInclusive<u16>> {
+        self.episode
+            .map(|start| start..=self.episode_end.unwrap_or(start))
+    }
+
+    // The specials and the decimal episodes are not counted as the regular episodes.
+    pub fn b_special(&self) -> bool {
+        self.kind != EpisodeKind::Regular || self.episode_decimal.is_some()
+    }
+
+    // The episode as written, such as `12`, `3-5` or `12.5`.
+    pub fn episode_label(&self) -> Option<String> {
+        if let Some(episode_decimal) = self.episode_decimal {
+            return Some(episode_decimal.to_string());
+        }
+        self.episode_range().map(|range| {
+            if range.start() == range.end() {
+                range.start().to_string()
+            } else {
+                format!("{}-{}", range.start(), range.end())
+            }
+        })
+    }
+
+    // The special as shown in the output, such as `SP`, `OVA 2` or `episode 12.5`.
+    pub fn special_label(&self) -> String {
+        match self.episode_label() {
+            Some(episode_label) => format!("{} {}", self.kind, episode_label),
+            None => self.kind.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiagnosticReason {
+    // The line cannot match any regex pattern.
+    NoMatch,
+    // The matched pattern does not capture a necessary group.
+    BadCapture { group: String },
+    InvalidNumber { group: String, text: String },
+    InvalidTime { group: String, text: String },
+    // The captured `kind` is none of the known episode kinds.
+    UnknownEpisodeKind { text: String },
+    // The captured logged_time matches none of the configured and built-in formats.
+    InvalidDateTime { text: String },
+    // The line is identical to an earlier one logged right before it or at the same time.
+    SuspectedDuplicate { first_line_number: usize },
+}
+
+impl Display for DiagnosticReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticReason::NoMatch => write!(f, "no regex pattern matches"),
+            DiagnosticReason::BadCapture { group } => {
+                write!(f, "the matched pattern captures no `{}`", group)
+            }
+            DiagnosticReason::InvalidNumber { group, text } => {
+                write!(f, "`{}` is not a valid number for `{}`", text, group)
+            }
+            DiagnosticReason::InvalidTime { group, text } => {
+                write!(f, "`{}` is not a valid time for `{}`", text, group)
+            }
+            DiagnosticReason::UnknownEpisodeKind { text } => {
+                write!(f, "`{}` is not a known episode kind for `kind`", text)
+            }
+            DiagnosticReason::SuspectedDuplicate { first_line_number } => {
+                write!(
+                    f,
+                    "the line is identical to line {}, it may be logged twice by accident",
+                    first_line_number
+                )
+            }
+            DiagnosticReason::InvalidDateTime { text } => {
+                write!(
+                    f,
+                    "`{}` matches none of the date/time formats for `logged_time`",
+                    text
+                )
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LineDiagnostic {
+    pub provenance: Provenance,
+    pub raw_line: String,
+    pub reason: DiagnosticReason,
+}
+
+impl Display for LineDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}\n    {}",
+            self.provenance, self.reason, self.raw_line
+        )
+    }
+}
+
+// The outcome of parsing a single line. The metadata can still exist while some fields of it
+// cannot be parsed, in such case the reasons are kept in the diagnostic list.
+// A date header line has no metadata, it sets the date for the following entries.
+pub struct ParsedLine {
+    pub metadata: Option<Metadata>,
+    pub date_header: Option<NaiveDate>,
+    pub diagnostic_list: Vec<DiagnosticReason>,
+}
+
+impl ParsedLine {
+    pub fn failed(reason: DiagnosticReason) -> Self {
+        ParsedLine {
+            metadata: None,
+            date_header: None,
+            diagnostic_list: vec![reason],
+        }
+    }
+}
+
+pub struct ParseResult {
+    pub metadata_list: Vec<Metadata>,
+    pub diagnostic_list: Vec<LineDiagnostic>,
 }
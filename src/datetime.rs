@@ -0,0 +1,245 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+// The built-in formats tried after the configured ones, the common ISO and Chinese forms.
+const BUILTIN_DATETIME_FORMAT_LIST: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d %H:%M",
+    "%Y.%m.%d %H:%M:%S",
+    "%Y.%m.%d %H:%M",
+    "%Y年%m月%d日 %H:%M:%S",
+    "%Y年%m月%d日 %H:%M",
+    "%Y年%m月%d日%H:%M",
+    "%Y年%m月%d日 %H时%M分",
+    "%Y年%m月%d日 %H点%M分",
+    "%Y年%m月%d日 %H点",
+];
+// The date-only values are treated as the start of the day.
+const BUILTIN_DATE_FORMAT_LIST: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d", "%Y年%m月%d日"];
+
+fn parse_datetime_with_format(datetime_str: &str, format: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(datetime_str, format)
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(datetime_str, format)
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+}
+
+// Try the given formats in order, then the built-in ones.
+pub fn parse_datetime(datetime_str: &str, format_list: &[String]) -> Option<NaiveDateTime> {
+    let datetime_str = datetime_str.trim();
+    format_list
+        .iter()
+        .map(String::as_str)
+        .chain(BUILTIN_DATETIME_FORMAT_LIST.iter().copied())
+        .chain(BUILTIN_DATE_FORMAT_LIST.iter().copied())
+        .find_map(|format| parse_datetime_with_format(datetime_str, format))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReferenceKind {
+    // The logged_time of the previous entry, the resolved time is on or after it.
+    PreviousEntry,
+    // The modification time of the watching log, the resolved time is on or before it.
+    FileModified,
+}
+
+const RELATIVE_DAY_LIST: &[(&str, i64)] = &[
+    ("今天", 0),
+    ("今日", 0),
+    ("今晚", 0),
+    ("today", 0),
+    ("tonight", 0),
+    ("昨天", -1),
+    ("昨日", -1),
+    ("昨晚", -1),
+    ("yesterday", -1),
+    ("前天", -2),
+];
+const WEEKDAY_PREFIX_LIST: &[&str] = &["星期", "礼拜", "禮拜", "周", "週"];
+
+fn parse_weekday(weekday_str: &str) -> Option<Weekday> {
+    match weekday_str {
+        "一" => Some(Weekday::Mon),
+        "二" => Some(Weekday::Tue),
+        "三" => Some(Weekday::Wed),
+        "四" => Some(Weekday::Thu),
+        "五" => Some(Weekday::Fri),
+        "六" => Some(Weekday::Sat),
+        "日" | "天" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn parse_time_of_day(time_str: &str) -> Option<NaiveTime> {
+    for format in ["%H:%M:%S", "%H:%M", "%H点%M分", "%H点%M", "%H时%M分"] {
+        if let Ok(time) = NaiveTime::parse_from_str(time_str, format) {
+            return Some(time);
+        }
+    }
+    let hour = time_str
+        .strip_suffix("点")
+        .or_else(|| time_str.strip_suffix("时"))?;
+    NaiveTime::from_hms_opt(hour.parse().ok()?, 0, 0)
+}
+
+// Split the text into the relative date and the optional time of day, with the number of days
+// between two dates the text can mean.
+fn parse_relative_date(
+    text: &str,
+    reference: NaiveDateTime,
+    reference_kind: ReferenceKind,
+) -> Option<(Option<NaiveDate>, &str, i64)> {
+    let reference_date = reference.date();
+    for (day_str, offset) in RELATIVE_DAY_LIST {
+        if let Some(rest) = text.strip_prefix(day_str) {
+            return Some((Some(reference_date + Duration::days(*offset)), rest, 1));
+        }
+    }
+    for prefix in WEEKDAY_PREFIX_LIST {
+        let Some(rest) = text.strip_prefix(prefix) else {
+            continue;
+        };
+        let weekday_len = rest.chars().next()?.len_utf8();
+        let weekday = parse_weekday(&rest[..weekday_len])?;
+        let days_from_reference = weekday.num_days_from_monday() as i64
+            - reference_date.weekday().num_days_from_monday() as i64;
+        let offset = match reference_kind {
+            ReferenceKind::PreviousEntry => days_from_reference.rem_euclid(7),
+            ReferenceKind::FileModified => -(-days_from_reference).rem_euclid(7),
+        };
+        return Some((
+            Some(reference_date + Duration::days(offset)),
+            &rest[weekday_len..],
+            7,
+        ));
+    }
+    // The bare time of day, the date is decided by the time.
+    Some((None, text, 1))
+}
+
+// Resolve the relative or partial logged_time, such as `昨天 21:00`, `今天`, `周六` or `21:30`,
+// against the reference time.
+pub fn resolve_relative_datetime(
+    text: &str,
+    reference: NaiveDateTime,
+    reference_kind: ReferenceKind,
+) -> Option<NaiveDateTime> {
+    let text = text.trim();
+    let (date, time_str, step_days) = parse_relative_date(text, reference, reference_kind)?;
+    let time_str = time_str.trim();
+    let time = if time_str.is_empty() {
+        None
+    } else {
+        Some(parse_time_of_day(time_str)?)
+    };
+
+    // The resolved time is kept on the right side of the reference, so the entries stay in the
+    // logged order.
+    match (date, time) {
+        (Some(date), None) => Some(match reference_kind {
+            ReferenceKind::PreviousEntry if date <= reference.date() => reference,
+            ReferenceKind::FileModified if date >= reference.date() => reference,
+            _ => date.and_time(NaiveTime::MIN),
+        }),
+        (date, Some(time)) => {
+            // A time on the wrong side is moved to the nearest such time on the right side.
+            let mut datetime = date.unwrap_or(reference.date()).and_time(time);
+            let step = Duration::days(step_days);
+            match reference_kind {
+                ReferenceKind::PreviousEntry => {
+                    while datetime < reference {
+                        datetime += step;
+                    }
+                }
+                ReferenceKind::FileModified => {
+                    while datetime > reference {
+                        datetime -= step;
+                    }
+                }
+            }
+            Some(datetime)
+        }
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod datetime_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_datetime_formats() {
+        let expected = NaiveDate::from_ymd_opt(2023, 10, 1)
+            .unwrap()
+            .and_hms_opt(21, 5, 0);
+        for datetime_str in [
+            "2023-10-01 21:05",
+            "2023-10-01 21:05:00 ",
+            "2023-10-01T21:05:00",
+            "2023/10/01 21:05",
+            "2023年10月1日 21:05",
+            "2023年10月1日 21点05分",
+        ] {
+            assert_eq!(
+                parse_datetime(datetime_str, &[]),
+                expected,
+                "{}",
+                datetime_str
+            );
+        }
+
+        let midnight = NaiveDate::from_ymd_opt(2023, 10, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0);
+        for date_str in ["2023-10-01", "2023/10/01", "2023年10月1日"] {
+            assert_eq!(parse_datetime(date_str, &[]), midnight, "{}", date_str);
+        }
+
+        assert_eq!(parse_datetime("01.10.2023 21:05", &[]), None);
+        assert_eq!(
+            parse_datetime("01.10.2023 21:05", &[String::from("%d.%m.%Y %H:%M")]),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_resolve_relative_datetime() {
+        // 2024-03-06 is a Wednesday.
+        let reference = NaiveDate::from_ymd_opt(2024, 3, 6)
+            .unwrap()
+            .and_hms_opt(20, 0, 0)
+            .unwrap();
+        let at = |day: u32, hour: u32, minute: u32| {
+            NaiveDate::from_ymd_opt(2024, 3, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+        };
+        let previous_entry =
+            |text| resolve_relative_datetime(text, reference, ReferenceKind::PreviousEntry);
+        let file_modified =
+            |text| resolve_relative_datetime(text, reference, ReferenceKind::FileModified);
+
+        // The relative dates before the previous entry are moved after it.
+        assert_eq!(previous_entry("昨天 21:00"), at(6, 21, 0));
+        assert_eq!(previous_entry("今天 19:00"), at(7, 19, 0));
+        assert_eq!(previous_entry("今天"), at(6, 20, 0));
+        assert_eq!(previous_entry("前天"), at(6, 20, 0));
+        assert_eq!(previous_entry("周三 19:00"), at(13, 19, 0));
+        assert_eq!(file_modified("今天 21:00"), at(5, 21, 0));
+        assert_eq!(file_modified("昨天 21:00"), at(5, 21, 0));
+        assert_eq!(previous_entry("周六"), at(9, 0, 0));
+        assert_eq!(file_modified("周六"), at(2, 0, 0));
+        assert_eq!(file_modified("星期三 9点"), at(6, 9, 0));
+        assert_eq!(previous_entry("21:30"), at(6, 21, 30));
+        assert_eq!(previous_entry("19:30"), at(7, 19, 30));
+        assert_eq!(file_modified("21:30"), at(5, 21, 30));
+        assert_eq!(previous_entry("someday"), None);
+        assert_eq!(previous_entry("周八"), None);
+    }
+}
@@ -0,0 +1,276 @@
+use super::config::{Config, PatternExample};
+use super::datatype::*;
+use super::datetime::parse_datetime;
+use super::parser::*;
+use super::pattern::PatternResult;
+use std::fmt::{Debug, Display};
+
+pub struct ExampleReport {
+    pub index: usize,
+    pub line: String,
+    // Each mismatch is a readable line of "field: expected ..., got ...".
+    pub mismatch_list: Vec<String>,
+    pub diagnostic_list: Vec<DiagnosticReason>,
+}
+
+impl ExampleReport {
+    pub fn b_passed(&self) -> bool {
+        self.mismatch_list.is_empty() && self.diagnostic_list.is_empty()
+    }
+}
+
+impl Display for ExampleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} examples[{}]: {}",
+            if self.b_passed() { "PASS" } else { "FAIL" },
+            self.index,
+            self.line
+        )?;
+        for diagnostic in &self.diagnostic_list {
+            write!(f, "\n    {}", diagnostic)?;
+        }
+        for mismatch in &self.mismatch_list {
+            write!(f, "\n    {}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+fn compare<T: PartialEq + Debug>(
+    mismatch_list: &mut Vec<String>,
+    field: &str,
+    expected: Option<T>,
+    actual: Option<T>,
+) {
+    if expected != actual {
+        mismatch_list.push(format!(
+            "{}: expected {:?}, got {:?}",
+            field, expected, actual
+        ));
+    }
+}
+
+// Compare the expected text with the parsed value, the expected text is parsed in the same way.
+fn compare_parsed<T: PartialEq + Debug>(
+    mismatch_list: &mut Vec<String>,
+    field: &str,
+    expected: &Option<String>,
+    parse_fn: impl Fn(&str) -> Option<T>,
+    actual: Option<T>,
+) {
+    let Some(expected_text) = expected else {
+        return;
+    };
+    match parse_fn(expected_text) {
+        Some(expected_value) => compare(mismatch_list, field, Some(expected_value), actual),
+        None => mismatch_list.push(format!(
+            "{}: the expected value `{}` cannot be parsed",
+            field, expected_text
+        )),
+    }
+}
+
+// Whether the example expects any field of an entry.
+fn b_entry_expected(example: &PatternExample) -> bool {
+    example.name.is_some()
+        || example.season.is_some()
+        || example.part.is_some()
+        || example.episode.is_some()
+        || example.episode_end.is_some()
+        || example.kind.is_some()
+        || example.time_at_episode.is_some()
+        || example.logged_time.is_some()
+        || example.rating.is_some()
+        || example.status.is_some()
+        || example.finished.is_some()
+}
+
+fn check_example(
+    index: usize,
+    example: &PatternExample,
+    metadata: Option<&Metadata>,
+    diagnostic_list: Vec<DiagnosticReason>,
+    logged_time_format_list: &[String],
+) -> ExampleReport {
+    let mut mismatch_list = Vec::new();
+    if let Some(metadata) = metadata {
+        if let Some(name) = &example.name {
+            compare(&mut mismatch_list, "name", Some(name), Some(&metadata.name));
+        }
+        if example.season.is_some() {
+            compare(
+                &mut mismatch_list,
+                "season",
+                example.season,
+                metadata.season,
+            );
+        }
+        if example.part.is_some() {
+            compare(&mut mismatch_list, "part", example.part, metadata.part);
+        }
+        if example.episode.is_some() {
+            compare(
+                &mut mismatch_list,
+                "episode",
+                example.episode,
+                metadata.episode,
+            );
+        }
+        if example.episode_end.is_some() {
+            compare(
+                &mut mismatch_list,
+                "episode_end",
+                example.episode_end,
+                metadata.episode_end,
+            );
+        }
+        if example.kind.is_some() {
+            compare(
+                &mut mismatch_list,
+                "kind",
+                example.kind,
+                Some(metadata.kind),
+            );
+        }
+        compare_parsed(
+            &mut mismatch_list,
+            "time_at_episode",
+            &example.time_at_episode,
+            parse_time,
+            metadata.time_at_episode,
+        );
+        compare_parsed(
+            &mut mismatch_list,
+            "logged_time",
+            &example.logged_time,
+            |text| parse_datetime(text, logged_time_format_list),
+            metadata.logged_time,
+        );
+        if example.rating.is_some() {
+            compare(
+                &mut mismatch_list,
+                "rating",
+                example.rating,
+                metadata.rating,
+            );
+        }
+        if example.status.is_some() {
+            compare(
+                &mut mismatch_list,
+                "status",
+                example.status,
+                Some(metadata.status),
+            );
+        }
+        if example.finished.is_some() {
+            compare(
+                &mut mismatch_list,
+                "finished",
+                example.finished,
+                Some(metadata.status == WatchStatus::Finished),
+            );
+        }
+    } else if diagnostic_list.is_empty() && b_entry_expected(example) {
+        // A line parsed cleanly without an entry is a date header.
+        mismatch_list.push(String::from("entry: expected an entry, got a date header"));
+    }
+    ExampleReport {
+        index,
+        line: example.line.clone(),
+        mismatch_list,
+        diagnostic_list,
+    }
+}
+
+// Parse all the examples with the same code path as parsing the watching log, without cache.
+pub fn run_examples(config: &Config) -> PatternResult<Vec<ExampleReport>> {
+    let mut parser = Parser::new(config.build_pattern_pool()?, None, 1, 1);
+    // Each example is parsed as a log of its own line, so a date header or a relative
+    // logged_time in one example does not change the others.
+    let report_list = config
+        .examples
+        .iter()
+        .enumerate()
+        .map(|(index, example)| {
+            let parse_result = parser.parse_metadata(std::slice::from_ref(&example.line));
+            let diagnostic_list = parse_result
+                .diagnostic_list
+                .into_iter()
+                .map(|diagnostic| diagnostic.reason)
+                .collect();
+            check_example(
+                index,
+                example,
+                parse_result.metadata_list.first(),
+                diagnostic_list,
+                &parser.pattern_pool.logged_time_format_list,
+            )
+        })
+        .collect();
+    Ok(report_list)
+}
+
+#[cfg(test)]
+mod example_tests {
+    use super::*;
+
+    #[test]
+    fn test_run_examples() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+reg_pattern_list:
+  - '(?<name>.+)\s第(?<episode>\d+)集\s(?<time_at_episode>\d{1,2}:\d{1,2})$'
+  - '(?<name>.+)\s看完$'
+finished_reg_pattern_list:
+  - '\s看完$'
+date_header_pattern_list:
+  - '^\d{4}-\d{2}-\d{2}$'
+examples:
+  - line: 'ABC 第3集 12:30'
+    name: ABC
+    episode: 3
+    time_at_episode: '12:30'
+    finished: false
+  - line: 'ABC 看完'
+    name: ABC
+    finished: false
+  - line: 'nothing'
+  - line: '2024-03-02'
+    name: ABC
+    episode: 3
+  - line: '2024-03-02'
+  - line: 'ABC 第3集 12:30'
+    logged_time: '2024-03-02'
+"#,
+        )
+        .unwrap();
+
+        let report_list = run_examples(&config).unwrap();
+        assert_eq!(report_list.len(), 6);
+        assert!(report_list[0].b_passed());
+        assert_eq!(
+            report_list[1].mismatch_list,
+            vec![String::from(
+                "finished: expected Some(false), got Some(true)"
+            )]
+        );
+        assert_eq!(
+            report_list[2].diagnostic_list,
+            vec![DiagnosticReason::NoMatch]
+        );
+        assert_eq!(
+            report_list[3].mismatch_list,
+            vec![String::from("entry: expected an entry, got a date header")]
+        );
+        assert!(report_list[4].b_passed());
+        // The date header of the example before does not apply.
+        assert_eq!(
+            report_list[5].mismatch_list,
+            vec![String::from(
+                "logged_time: expected Some(2024-03-02T00:00:00), got None"
+            )]
+        );
+    }
+}
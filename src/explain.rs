@@ -0,0 +1,319 @@
+use super::datatype::{DiagnosticReason, WatchStatus};
+use super::datetime::*;
+use super::notation::{
+    parse_episode_decimal, parse_episode_kind, parse_number, parse_part, parse_rating,
+};
+use super::parser::*;
+use super::pattern::{CompiledPatternList, PatternPool};
+use chrono::{Local, NaiveDate};
+use std::fmt::Display;
+
+pub struct PatternMatch {
+    pub index: usize,
+    pub pattern: String,
+    pub b_matched: bool,
+}
+
+pub struct CaptureExplanation {
+    pub group: String,
+    pub raw_text: String,
+    // The value after parsing, None for the groups kept as text, such as name and note.
+    pub parsed_value: Option<String>,
+}
+
+// The matches of a status pattern list, such as finished_reg_pattern_list.
+pub struct StatusPatternMatch {
+    pub status: WatchStatus,
+    pub list: &'static str,
+    pub pattern_match_list: Vec<PatternMatch>,
+    pub winner_index: Option<usize>,
+}
+
+// How a single line is parsed, step by step.
+pub struct Explanation {
+    pub line: String,
+    pub date_header_match_list: Vec<PatternMatch>,
+    pub date_header_winner_index: Option<usize>,
+    // The date set by the line if it is a date header.
+    pub date_header: Option<NaiveDate>,
+    pub reg_pattern_match_list: Vec<PatternMatch>,
+    pub winner_index: Option<usize>,
+    pub capture_list: Vec<CaptureExplanation>,
+    pub status_pattern_match_list: Vec<StatusPatternMatch>,
+    pub status: WatchStatus,
+    pub diagnostic_list: Vec<DiagnosticReason>,
+}
+
+fn explain_pattern_list(
+    compiled_pattern_list: &CompiledPatternList,
+    line: &str,
+) -> (Vec<PatternMatch>, Option<usize>) {
+    let match_index_list = compiled_pattern_list.match_index_list(line);
+    let pattern_match_list = compiled_pattern_list
+        .pattern_list
+        .iter()
+        .enumerate()
+        .map(|(index, pattern)| PatternMatch {
+            index,
+            pattern: pattern.clone(),
+            b_matched: match_index_list.contains(&index),
+        })
+        .collect();
+    (pattern_match_list, match_index_list.first().copied())
+}
+
+fn format_parsed<T: std::fmt::Display>(value: Option<T>) -> Option<String> {
+    Some(match value {
+        Some(value) => value.to_string(),
+        None => String::from("invalid"),
+    })
+}
+
+// The relative logged_time depends on the previous entries, a single line is resolved against now
+// and marked as inferred. In the log it is resolved against the previous entry and may be moved
+// after it to keep the order.
+fn explain_logged_time(raw_text: &str, format_list: &[String]) -> Option<String> {
+    if let Some(logged_time) = parse_datetime(raw_text, format_list) {
+        return Some(format!("{:?}", logged_time));
+    }
+    resolve_relative_datetime(
+        raw_text.trim(),
+        Local::now().naive_local(),
+        ReferenceKind::FileModified,
+    )
+    .map(|logged_time| {
+        format!(
+            "{:?} (inferred, relative to now here, to the previous entry in the log)",
+            logged_time
+        )
+    })
+}
+
+pub fn explain_line(line: &str, pattern_pool: &PatternPool) -> Explanation {
+    let (date_header_match_list, date_header_winner_index) =
+        explain_pattern_list(&pattern_pool.date_header_pattern_list, line);
+    let (reg_pattern_match_list, winner_index) =
+        explain_pattern_list(&pattern_pool.reg_pattern_list, line);
+    let status_pattern_match_list: Vec<StatusPatternMatch> = pattern_pool
+        .status_reg_pattern_list()
+        .into_iter()
+        .map(|(status, list, status_reg_pattern_list)| {
+            let (pattern_match_list, winner_index) =
+                explain_pattern_list(status_reg_pattern_list, line);
+            StatusPatternMatch {
+                status,
+                list,
+                pattern_match_list,
+                winner_index,
+            }
+        })
+        .collect();
+    let status = status_pattern_match_list
+        .iter()
+        .find(|status_pattern_match| status_pattern_match.winner_index.is_some())
+        .map_or(WatchStatus::Watching, |status_pattern_match| {
+            status_pattern_match.status
+        });
+
+    let mut capture_list = Vec::new();
+    let parsed_line = parse_line(line, 1, 0, pattern_pool);
+    let mut diagnostic_list = parsed_line.diagnostic_list;
+    // The date header wins against all the reg patterns.
+    if let Some(winner_index) = winner_index.filter(|_| date_header_winner_index.is_none()) {
+        let re = pattern_pool.reg_pattern_list.regex(winner_index);
+        if let Some(caps) = re.captures(line) {
+            for group in re.capture_names().flatten() {
+                let Some(m) = caps.name(group) else {
+                    continue;
+                };
+                let raw_text = m.as_str();
+                let parsed_value = match group {
+                    "season" | "episode_start" | "episode_end" => {
+                        format_parsed(parse_number(raw_text))
+                    }
+                    "part" => format_parsed(parse_part(raw_text)),
+                    "kind" => format_parsed(parse_episode_kind(raw_text)),
+                    "episode" => match parse_episode_decimal(raw_text) {
+                        Some(episode_decimal) => Some(format!("{} (decimal)", episode_decimal)),
+                        None => format_parsed(parse_number_range(raw_text).map(|(start, end)| {
+                            if start == end {
+                                start.to_string()
+                            } else {
+                                format!("{}-{}", start, end)
+                            }
+                        })),
+                    },
+                    "time_at_episode" => format_parsed(parse_time(raw_text)),
+                    "rating" => format_parsed(
+                        parse_rating(raw_text, &pattern_pool.rating_format)
+                            .map(|rating| format!("{:.1}/10", rating)),
+                    ),
+                    "logged_time" => Some(
+                        explain_logged_time(raw_text, &pattern_pool.logged_time_format_list)
+                            .unwrap_or_else(|| {
+                                diagnostic_list.push(DiagnosticReason::InvalidDateTime {
+                                    text: raw_text.trim().to_string(),
+                                });
+                                String::from("invalid")
+                            }),
+                    ),
+                    _ => None,
+                };
+                capture_list.push(CaptureExplanation {
+                    group: group.to_string(),
+                    raw_text: raw_text.to_string(),
+                    parsed_value,
+                });
+            }
+        }
+    }
+
+    Explanation {
+        line: line.to_string(),
+        date_header_match_list,
+        date_header_winner_index,
+        date_header: parsed_line.date_header,
+        reg_pattern_match_list,
+        winner_index,
+        capture_list,
+        status_pattern_match_list,
+        status,
+        diagnostic_list,
+    }
+}
+
+fn write_pattern_match_list(
+    f: &mut std::fmt::Formatter<'_>,
+    list: &str,
+    pattern_match_list: &[PatternMatch],
+    winner_index: Option<usize>,
+) -> std::fmt::Result {
+    writeln!(f, "{}:", list)?;
+    for pattern_match in pattern_match_list {
+        let status = if Some(pattern_match.index) == winner_index {
+            "WINNER "
+        } else if pattern_match.b_matched {
+            "matched"
+        } else {
+            "-      "
+        };
+        writeln!(
+            f,
+            "  [{}] {} {}",
+            pattern_match.index, status, pattern_match.pattern
+        )?;
+    }
+    Ok(())
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "line: {}", self.line)?;
+        if !self.date_header_match_list.is_empty() {
+            write_pattern_match_list(
+                f,
+                "date_header_pattern_list",
+                &self.date_header_match_list,
+                self.date_header_winner_index,
+            )?;
+        }
+        if let Some(date_header) = self.date_header {
+            writeln!(
+                f,
+                "date header: the following entries without logged_time are logged on {}",
+                date_header
+            )?;
+        }
+        write_pattern_match_list(
+            f,
+            "reg_pattern_list",
+            &self.reg_pattern_match_list,
+            self.winner_index,
+        )?;
+        match self.winner_index {
+            _ if self.date_header_winner_index.is_some() => {}
+            Some(winner_index) => {
+                writeln!(f, "captures of reg_pattern_list[{}]:", winner_index)?;
+                for capture in &self.capture_list {
+                    write!(f, "  {:<16} {:?}", capture.group, capture.raw_text)?;
+                    if let Some(parsed_value) = &capture.parsed_value {
+                        write!(f, " -> {}", parsed_value)?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+            None => writeln!(f, "no pattern matches, the line is skipped")?,
+        }
+        // The first list with a winner sets the status, the empty optional lists are not shown.
+        let mut status_winner = None;
+        for status_pattern_match in &self.status_pattern_match_list {
+            if status_pattern_match.status != WatchStatus::Finished
+                && status_pattern_match.pattern_match_list.is_empty()
+            {
+                continue;
+            }
+            write_pattern_match_list(
+                f,
+                status_pattern_match.list,
+                &status_pattern_match.pattern_match_list,
+                status_pattern_match.winner_index,
+            )?;
+            if status_winner.is_none()
+                && let Some(winner_index) = status_pattern_match.winner_index
+            {
+                status_winner = Some((status_pattern_match.list, winner_index));
+            }
+        }
+        match status_winner {
+            Some((list, index)) => {
+                writeln!(f, "status: {}, set by {}[{}]", self.status, list, index)?
+            }
+            None => writeln!(f, "status: {}", self.status)?,
+        }
+        for diagnostic in &self.diagnostic_list {
+            writeln!(f, "problem: {}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod explain_tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_line() {
+        let pattern_pool = PatternPool::new(
+            vec![
+                String::from(r"(?<name>.+)\s第(?<episode>\S+)集$"),
+                String::from(r"(?<name>.+)\s(?<note>.+)$"),
+            ],
+            vec![String::from(r"看完$"), String::from(r"集$")],
+        )
+        .unwrap();
+
+        let explanation = explain_line("ABC 第十二集", &pattern_pool);
+        assert_eq!(explanation.winner_index, Some(0));
+        assert!(explanation.reg_pattern_match_list[1].b_matched);
+        assert_eq!(explanation.capture_list.len(), 2);
+        assert_eq!(explanation.capture_list[1].group, "episode");
+        assert_eq!(explanation.capture_list[1].raw_text, "十二");
+        assert_eq!(
+            explanation.capture_list[1].parsed_value,
+            Some(String::from("12"))
+        );
+        assert_eq!(
+            explanation.status_pattern_match_list[0].winner_index,
+            Some(1)
+        );
+        assert_eq!(explanation.status, WatchStatus::Finished);
+        assert!(explanation.diagnostic_list.is_empty());
+
+        let explanation = explain_line("ABC 第x集", &pattern_pool);
+        assert_eq!(
+            explanation.capture_list[1].parsed_value,
+            Some(String::from("invalid"))
+        );
+        assert_eq!(explanation.diagnostic_list.len(), 1);
+    }
+}
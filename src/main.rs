@@ -1,67 +1,491 @@
 mod cache_db;
 use cache_db::Cache;
+use std::collections::HashSet;
+mod config;
+use config::*;
 mod datatype;
+use datatype::WatchStatus;
+mod datetime;
+mod example;
+use example::run_examples;
+mod explain;
+use explain::explain_line;
 mod logger;
 use logger::*;
-use serde::Deserialize;
 use std::process;
+mod notation;
 mod parser;
 mod parser_task_manager;
 use parser::*;
+mod pattern;
+mod stale;
+use stale::*;
 mod stats;
+mod timeline;
+use chrono::{DateTime, Local};
 use clap::Parser as ClapParser;
-use clap::ValueEnum;
+use clap::{Subcommand, ValueEnum};
 use stats::*;
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::{fs, io};
+use timeline::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, ValueEnum)]
 pub enum Mode {
     UnFinished,
     Query,
     All,
+    Rated,
+    Next,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(subcommand, about = "Commands for the config file")]
+    Config(ConfigCommand),
+    #[command(about = "Show how a single line is parsed by the patterns in the config file")]
+    Explain { line: String },
+    #[command(about = "List the unfinished watching untouched for a while")]
+    Stale {
+        #[arg(
+            long,
+            help = "The watching untouched for more days is stale, the default is stale_days in the config file or 30"
+        )]
+        days: Option<u32>,
+        #[arg(
+            long,
+            help = "Ask for each stale watching whether to append a dropped entry made from dropped_entry_template"
+        )]
+        drop: bool,
+    },
+    #[command(about = "Show the timeline of all the entries of a title across the seasons")]
+    Show {
+        name: String,
+        #[arg(
+            long,
+            help = "The sessions more days apart are shown with a gap between them, the default is 14"
+        )]
+        gap_days: Option<u32>,
+    },
+    #[command(subcommand, about = "Commands for the parsing cache")]
+    Cache(CacheCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    #[command(
+        about = "Validate the patterns in the config file without parsing, the lines of --filename are used as samples if given"
+    )]
+    Check,
+    #[command(
+        about = "Parse the examples in the config file and compare them with the expected values"
+    )]
+    Test,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    #[command(about = "Show the row count, the file size and the hit rate of the last run")]
+    Stats,
+    #[command(
+        about = "Delete the rows not referenced by the lines of --filename with the current patterns"
+    )]
+    Prune,
+    #[command(about = "Delete all the rows")]
+    Clear,
+    #[command(about = "Give the space of the deleted rows back to the file system")]
+    Vacuum,
 }
 
 #[derive(ClapParser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     // config dir
     #[arg(
         short,
         long,
+        global = true,
         help = "If not set, we will use your system's config path"
     )]
     config_path: Option<String>,
     // file
-    #[arg(short, long)]
-    filename: String,
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "The watching log file, it is necessary when no command is given"
+    )]
+    filename: Option<String>,
 
     #[arg(
         short,
         long,
+        global = true,
         value_enum, default_value_t = LogLevel::Warn,
         help = "If not set, we will use the warning leve. The options are: error, warn, info, debug.\n"
     )]
     log_level: LogLevel,
 
+    #[arg(
+        long,
+        global = true,
+        help = "If not set, we will use cache.db under your system's cache path"
+    )]
+    cache_path: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Parse every line without reading or writing the cache"
+    )]
+    no_cache: bool,
+
     #[arg(
         short,
         long,
         value_enum,
         default_value_t = Mode::UnFinished,
-        help = "We have three modes right now,\n\tunfinished(default): list all unifhished watching\n\tquery: list all matching watching with give query name\n\tall: list all watching.\n")]
+        help = "We have five modes right now,\n\tunfinished(default): list all unifhished watching\n\tquery: list all matching watching with give query name\n\tall: list all watching.\n\trated: list all watching finished at least once with a rating, the best rated first.\n\tnext: list where to continue each unfinished watching, the most recently watched first.\n")]
     mode: Mode,
 
     #[arg(short, long, required_if_eq("mode", "query"))]
     query_name: Option<String>,
+
+    #[arg(
+        long,
+        help = "Exit with a non-zero code if any line cannot be parsed cleanly"
+    )]
+    strict: bool,
+
+    #[arg(
+        long,
+        help = "Track the parts of a split season, such as `Season 2 Part 2`, as one season"
+    )]
+    merge_parts: bool,
+
+    #[arg(
+        long,
+        help = "Report the identical lines logged right after each other or at the same time as suspected duplicates"
+    )]
+    flag_duplicates: bool,
+
+    #[arg(
+        short = 'w',
+        long = "where",
+        value_name = "KEY=VALUE",
+        help = "Only list the watching whose extra field KEY contains VALUE, such as `platform=netflix`, can be repeated"
+    )]
+    where_list: Vec<String>,
+
+    #[arg(
+        long,
+        help = "The output template, such as `{title}: {episode_count} on {field.platform}`. The placeholders are title, name, season, part, status, episode_count, special_count and field.<KEY> for the extra fields"
+    )]
+    format: Option<String>,
+}
+
+// Run all the checks on the config and print the problems, return the number of errors.
+fn run_config_check(config: &Config, sample_lines: &[String]) -> usize {
+    let problem_list = config.check(sample_lines);
+    let count = |severity| {
+        problem_list
+            .iter()
+            .filter(|problem| problem.severity == severity)
+            .count()
+    };
+    let error_count = count(Severity::Error);
+    for problem in &problem_list {
+        println!("{}", problem);
+    }
+    println!(
+        "{} pattern(s) checked: {} error(s), {} warning(s)",
+        config.reg_pattern_list.len()
+            + config.finished_reg_pattern_list.len()
+            + config.dropped_reg_pattern_list.len()
+            + config.on_hold_reg_pattern_list.len()
+            + config.plan_to_watch_reg_pattern_list.len(),
+        error_count,
+        count(Severity::Warning)
+    );
+    error_count
+}
+
+// Run all the examples in the config and print the reports, return the number of failures.
+fn run_config_test(config: &Config) -> usize {
+    let report_list = match run_examples(config) {
+        Ok(report_list) => report_list,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let failed_count = report_list
+        .iter()
+        .filter(|report| !report.b_passed())
+        .count();
+    for report in &report_list {
+        println!("{}", report);
+    }
+    println!(
+        "{} example(s): {} passed, {} failed",
+        report_list.len(),
+        report_list.len() - failed_count,
+        failed_count
+    );
+    failed_count
+}
+
+fn format_watching(watching: &StatsInfo) -> String {
+    let mut text = format!("{} - {}", watching.title(), watching.status);
+    if watching.b_rewatching() {
+        text += &format!(" ({})", watching.cycle_label());
+    }
+    if watching.watched_times > 1 {
+        text += &format!(", watched {} times", watching.watched_times);
+    }
+    if !watching.watched_episode_set.is_empty() {
+        text += &format!(
+            ", {} episode(s) watched",
+            watching.watched_episode_set.len()
+        );
+    }
+    if let Some(rating) = watching.latest_rating() {
+        text += &format!(", rated {:.1}/10", rating);
+    }
+    if !watching.special_list.is_empty() {
+        text += &format!(
+            ", {} special(s) watched: {}",
+            watching.special_list.len(),
+            watching.special_list.join(", ")
+        );
+    }
+    text
+}
+
+fn format_unfinished(watching: &StatsInfo) -> String {
+    let mut text = watching.title();
+    if watching.b_rewatching() {
+        text += &format!(" ({})", watching.cycle_label());
+    }
+    if watching.status == WatchStatus::OnHold {
+        text += " (on hold)";
+    }
+    text
+}
+
+// One line per viewing cycle with the number of entries and the logged dates.
+fn format_cycle_list(watching: &StatsInfo) -> Vec<String> {
+    watching
+        .cycle_list()
+        .iter()
+        .enumerate()
+        .map(|(index, cycle)| {
+            let label = if index == 0 {
+                String::from("first watch")
+            } else {
+                format!("rewatch {}", index)
+            };
+            let mut text = format!("    {}: {} entries", label, cycle.len());
+            let mut logged_date_list = cycle
+                .iter()
+                .filter_map(|entry| entry.logged_time.map(|logged_time| logged_time.date()));
+            if let Some(first_date) = logged_date_list.next() {
+                let last_date = logged_date_list.next_back().unwrap_or(first_date);
+                text += &format!(", {} to {}", first_date, last_date);
+            }
+            text
+        })
+        .collect()
+}
+
+fn format_next(watching: &StatsInfo, resume_point: &ResumePoint) -> String {
+    let mut text = format_unfinished(watching);
+    match (resume_point.episode, resume_point.time_at_episode) {
+        (Some(episode), Some(time_at_episode)) => {
+            text += &format!(
+                " - episode {} at {}",
+                episode,
+                format_time_at_episode(time_at_episode)
+            )
+        }
+        (Some(episode), None) => {
+            text += &format!(" - episode {} finished", episode);
+            if let Some(next_episode) = resume_point.next_episode {
+                text += &format!(", next is episode {}", next_episode);
+            }
+        }
+        _ => {}
+    }
+    if let Some(last_watched_time) = resume_point.last_watched_time {
+        text += &format!(
+            ", last watched on {}",
+            last_watched_time.format("%a %Y-%m-%d")
+        );
+    }
+    text
+}
+
+fn format_rating(watching: &StatsInfo) -> String {
+    let mut text = format!(
+        "{} - {:.1}/10",
+        watching.title(),
+        watching.latest_rating().unwrap_or_default()
+    );
+    if watching.rating_list.len() > 1 {
+        text += &format!(
+            " (average {:.1} over {} ratings)",
+            watching.average_rating().unwrap_or_default(),
+            watching.rating_list.len()
+        );
+    }
+    text
 }
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    reg_pattern_list: Vec<String>,
-    finished_reg_pattern_list: Vec<String>,
-    max_thread_num: Option<usize>,
-    min_task_num_per_thread: Option<usize>,
+// List the stale watching, and append the dropped entries confirmed by the user to the log.
+fn run_stale(
+    config: &Config,
+    stats: &Stats,
+    pattern_pool: &pattern::PatternPool,
+    file_path: &PathBuf,
+    days: Option<u32>,
+    b_drop: bool,
+    b_merge_parts: bool,
+) -> io::Result<()> {
+    let now = Local::now().naive_local();
+    let stale_days = days.or(config.stale_days).unwrap_or(DEFAULT_STALE_DAYS);
+    let abandoned_days = config
+        .abandoned_days
+        .unwrap_or(DEFAULT_ABANDONED_DAYS)
+        .max(stale_days);
+    let stale_list = find_stale(stats, now, stale_days, abandoned_days);
+    for stale in &stale_list {
+        println!("{}", stale);
+    }
+    if !b_drop || stale_list.is_empty() {
+        return Ok(());
+    }
+    let Some(template) = &config.dropped_entry_template else {
+        eprintln!("Set dropped_entry_template in the config file to append the dropped entries.");
+        process::exit(1);
+    };
+
+    let mut appended_line_list = Vec::new();
+    for stale in &stale_list {
+        let line = render_dropped_line(template, &stale.statsinfo, now);
+        if let Err(reason) =
+            check_dropped_line(&line, pattern_pool, &stale.statsinfo, b_merge_parts)
+        {
+            eprintln!("Skip `{}`, it is not a dropped entry: {}", line, reason);
+            continue;
+        }
+        print!("Append `{}` to {}? [y/N] ", line, file_path.display());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            break;
+        }
+        if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            appended_line_list.push(line);
+        }
+    }
+    if appended_line_list.is_empty() {
+        return Ok(());
+    }
+
+    let b_ends_with_newline = fs::read(file_path)?
+        .last()
+        .is_none_or(|byte| *byte == b'\n');
+    let mut file = fs::OpenOptions::new().append(true).open(file_path)?;
+    if !b_ends_with_newline {
+        writeln!(file)?;
+    }
+    for line in &appended_line_list {
+        writeln!(file, "{}", line)?;
+    }
+    println!(
+        "{} dropped entry(s) appended to {}",
+        appended_line_list.len(),
+        file_path.display()
+    );
+    Ok(())
+}
+
+fn run_cache_command(
+    cache_command: &CacheCommand,
+    cache_path: &PathBuf,
+    config: &Config,
+    file_path: Option<&PathBuf>,
+) -> io::Result<()> {
+    let to_io_error = |e| io::Error::other(format!("{}: {}", cache_path.display(), e));
+    let cache = Cache::new(&cache_path.to_string_lossy()).map_err(to_io_error)?;
+    let file_size = || fs::metadata(cache_path).map(|metadata| metadata.len());
+    match cache_command {
+        CacheCommand::Stats => {
+            println!("Cache: {}", cache_path.display());
+            if cache.found_schema_version() == cache_db::SCHEMA_VERSION {
+                println!("Schema version: {}", cache_db::SCHEMA_VERSION);
+            } else {
+                println!(
+                    "Schema version: {} (upgraded from {})",
+                    cache_db::SCHEMA_VERSION,
+                    cache.found_schema_version()
+                );
+            }
+            println!("Rows: {}", cache.row_count().map_err(to_io_error)?);
+            println!("Size: {} bytes", file_size()?);
+            match cache.last_run().map_err(to_io_error)? {
+                Some(run_stats) => println!(
+                    "Last run: {} of {} lines from the cache ({:.1}%)",
+                    run_stats.hit_count,
+                    run_stats.line_count,
+                    run_stats.hit_rate() * 100.0
+                ),
+                None => println!("Last run: none"),
+            }
+        }
+        CacheCommand::Prune => {
+            let Some(file_path) = file_path else {
+                eprintln!(
+                    "Pruning keeps the rows of the watching log file, please specify one with --filename."
+                );
+                process::exit(1);
+            };
+            let pattern_pool = config
+                .build_pattern_pool()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let kept_id_set: HashSet<u64> = read_lines(file_path)
+                .iter()
+                .map(|line| pattern_pool.line_hash(line))
+                .collect();
+            let deleted_count = cache.prune(&kept_id_set).map_err(to_io_error)?;
+            println!("Deleted {} rows", deleted_count);
+        }
+        CacheCommand::Clear => {
+            let deleted_count = cache.clear().map_err(to_io_error)?;
+            println!("Deleted {} rows", deleted_count);
+        }
+        CacheCommand::Vacuum => {
+            let size_before = file_size()?;
+            cache.vacuum().map_err(to_io_error)?;
+            println!("Size: {} -> {} bytes", size_before, file_size()?);
+        }
+    }
+    Ok(())
+}
+
+fn read_lines(file_path: &PathBuf) -> Vec<String> {
+    match fs::read_to_string(file_path) {
+        Ok(contents) => contents.lines().map(|line| line.to_string()).collect(),
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", file_path.display(), e);
+            process::exit(1);
+        }
+    }
 }
 
 fn main() -> io::Result<()> {
@@ -69,8 +493,6 @@ fn main() -> io::Result<()> {
 
     let our_program_name = "watching_log_parser";
 
-    let file_path = PathBuf::from(args.filename);
-
     let config_path = if let Some(specified_config_path) = args.config_path {
         PathBuf::from(specified_config_path)
     } else if let Some(config_dir) = dirs_2::config_dir() {
@@ -82,7 +504,9 @@ fn main() -> io::Result<()> {
         process::exit(1);
     };
 
-    let cache_path = if let Some(cache_dir) = dirs_2::cache_dir() {
+    let cache_path = if let Some(specified_cache_path) = &args.cache_path {
+        PathBuf::from(specified_cache_path)
+    } else if let Some(cache_dir) = dirs_2::cache_dir() {
         let our_cache_dir = cache_dir.join(our_program_name);
         if !our_cache_dir.exists() {
             fs::create_dir_all(&our_cache_dir)?;
@@ -95,93 +519,150 @@ fn main() -> io::Result<()> {
 
     logger_init(args.log_level);
 
-    let config_json_data = fs::read_to_string(config_path).unwrap();
-    let config: Config = serde_yaml::from_str(&config_json_data)
+    let config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let file_path = args.filename.map(PathBuf::from);
+
+    if let Some(Command::Explain { line }) = &args.command {
+        let pattern_pool = config
+            .build_pattern_pool()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        print!("{}", explain_line(line, &pattern_pool));
+        return Ok(());
+    }
+
+    if let Some(Command::Config(config_command)) = args.command {
+        match config_command {
+            ConfigCommand::Check => {
+                let mut sample_lines = file_path.as_ref().map(read_lines).unwrap_or_default();
+                sample_lines.extend(config.examples.iter().map(|example| example.line.clone()));
+                if run_config_check(&config, &sample_lines) > 0 {
+                    process::exit(1);
+                }
+            }
+            ConfigCommand::Test => {
+                if run_config_test(&config) > 0 {
+                    process::exit(1);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Cache(cache_command)) = &args.command {
+        return run_cache_command(cache_command, &cache_path, &config, file_path.as_ref());
+    }
+
+    let Some(file_path) = file_path else {
+        eprintln!("The watching log file is necessary, please specify one with --filename.");
+        process::exit(1);
+    };
+    let lines = read_lines(&file_path);
+
+    let pattern_pool = config
+        .build_pattern_pool()
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    let contents = fs::read_to_string(file_path).unwrap();
 
     let mut parser = Parser::new(
-        config.reg_pattern_list,
-        config.finished_reg_pattern_list,
-        Cache::new(cache_path.to_str().unwrap()).ok(),
+        pattern_pool,
+        if args.no_cache {
+            None
+        } else {
+            match Cache::new(&cache_path.to_string_lossy()) {
+                Ok(cache) => Some(cache),
+                Err(e) => {
+                    log_warn!("Parsing without the cache {}: {}", cache_path.display(), e);
+                    None
+                }
+            }
+        },
         config.max_thread_num.unwrap_or(1),
         config.min_task_num_per_thread.unwrap_or(1),
     );
+    parser.source_path = Some(Arc::from(file_path.as_path()));
+    parser.b_flag_duplicates = args.flag_duplicates;
+    parser.file_modified_time = fs::metadata(&file_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(|modified| DateTime::<Local>::from(modified).naive_local());
 
-    let lines: Vec<String> = contents.lines().map(|line| line.to_string()).collect();
-
-    let metadata_list = parser.parse_metadata(&lines);
-    let stats = Stats::new(metadata_list);
-    if args.mode == Mode::UnFinished {
+    let parse_result = parser.parse_metadata(&lines);
+    let diagnostic_list = parse_result.diagnostic_list;
+    let stats = Stats::new(parse_result.metadata_list, args.merge_parts);
+    let b_selected = |watching: &StatsInfo| {
+        args.where_list
+            .iter()
+            .all(|condition| watching.b_field_matched(condition))
+    };
+    let render = |watching: &StatsInfo, default_text: String| match &args.format {
+        Some(template) => watching.render(template),
+        None => default_text,
+    };
+    if let Some(Command::Stale { days, drop }) = &args.command {
+        run_stale(
+            &config,
+            &stats,
+            &parser.pattern_pool,
+            &file_path,
+            *days,
+            *drop,
+            args.merge_parts,
+        )?;
+    } else if let Some(Command::Show { name, gap_days }) = &args.command {
+        let timeline = build_timeline(&stats, name, gap_days.unwrap_or(DEFAULT_GAP_DAYS));
+        if timeline.is_empty() {
+            println!("No record found for {}", name);
+        }
+        for event in &timeline {
+            println!("{}", event);
+        }
+    } else if args.mode == Mode::UnFinished {
         let unfinished_wathcing_list = stats.stats_unfinished();
-        for unfinished_watching in unfinished_wathcing_list {
-            if unfinished_watching.season.is_some() {
-                println!(
-                    "{} season {}",
-                    unfinished_watching.name,
-                    unfinished_watching.season.unwrap()
-                );
-            } else {
-                println!("{}", unfinished_watching.name);
-            }
+        for unfinished_watching in unfinished_wathcing_list.iter().filter(|w| b_selected(w)) {
+            println!(
+                "{}",
+                render(unfinished_watching, format_unfinished(unfinished_watching))
+            );
         }
     } else if args.mode == Mode::All {
         let all_wathcing_list = stats.stats_all();
-        for watching in all_wathcing_list {
-            if watching.season.is_some() {
-                println!(
-                    "{} season {} - {}",
-                    watching.name,
-                    watching.season.unwrap(),
-                    if watching.b_finished {
-                        "finished"
-                    } else {
-                        "unfinished"
-                    }
-                );
-            } else {
-                println!(
-                    "{} - {}",
-                    watching.name,
-                    if watching.b_finished {
-                        "finished"
-                    } else {
-                        "unfinished"
-                    }
-                );
-            }
+        for watching in all_wathcing_list.iter().filter(|w| b_selected(w)) {
+            println!("{}", render(watching, format_watching(watching)));
+        }
+    } else if args.mode == Mode::Rated {
+        let rated_watching_list = stats.stats_rated();
+        for watching in rated_watching_list.iter().filter(|w| b_selected(w)) {
+            println!("{}", render(watching, format_rating(watching)));
+        }
+    } else if args.mode == Mode::Next {
+        let next_list = stats.stats_next();
+        for (watching, resume_point) in next_list.iter().filter(|(w, _)| b_selected(w)) {
+            println!("{}", render(watching, format_next(watching, resume_point)));
         }
     } else if args.mode == Mode::Query {
-        let query_name = args.query_name.unwrap();
-        let matching_watching_list = stats.query_by_name(&query_name);
-        if matching_watching_list.len() > 1 {
+        let query_name = args.query_name.as_deref().unwrap();
+        let matching_watching_list: Vec<StatsInfo> = stats
+            .query_by_name(query_name)
+            .into_iter()
+            .filter(|w| b_selected(w))
+            .collect();
+        if !matching_watching_list.is_empty() {
             println!(
                 "Found {} matching records for {}:",
                 matching_watching_list.len(),
                 query_name
             );
-            for watching in matching_watching_list {
-                if watching.season.is_some() {
-                    println!(
-                        "{} season {} - {}",
-                        watching.name,
-                        watching.season.unwrap(),
-                        if watching.b_finished {
-                            "finished"
-                        } else {
-                            "unfinished"
-                        }
-                    );
-                } else {
-                    println!(
-                        "{} - {}",
-                        watching.name,
-                        if watching.b_finished {
-                            "finished"
-                        } else {
-                            "unfinished"
-                        }
-                    );
+            for watching in &matching_watching_list {
+                println!("{}", render(watching, format_watching(watching)));
+                if args.format.is_none() && watching.b_rewatching() {
+                    for cycle_text in format_cycle_list(watching) {
+                        println!("{}", cycle_text);
+                    }
                 }
             }
         } else {
@@ -189,5 +670,19 @@ fn main() -> io::Result<()> {
         }
     }
 
+    if !diagnostic_list.is_empty() {
+        eprintln!(
+            "{} problem(s) found while parsing {} lines:",
+            diagnostic_list.len(),
+            lines.len()
+        );
+        for diagnostic in &diagnostic_list {
+            eprintln!("  {}", diagnostic);
+        }
+        if args.strict {
+            process::exit(2);
+        }
+    }
+
     Ok(())
 }
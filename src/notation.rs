@@ -0,0 +1,388 @@
+use super::datatype::EpisodeKind;
+use chinese_number::from_chinese_to_u16;
+use serde::Deserialize;
+
+// The built-in episode notations, each one is a pattern appended after the user's
+// reg_pattern_list, so the user's patterns always have a higher priority.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinNotation {
+    // `Show S02E05`, `Show s2e5-7`
+    SeasonEpisodeCode,
+    // `Show 第3話`, `Show 第2期 第十二話`
+    JapaneseEpisode,
+    // `Show Season II`, `Show Season 2 Part 2 Episode 5`
+    EnglishSeason,
+}
+
+// The optional tail shared by all the built-in patterns: the time at the episode, a note such as
+// the finished marker, and the logged time at the end.
+const SUFFIX_PATTERN: &str = r"(?:\s+(?<time_at_episode>\d{1,2}:\d{2}(?::\d{2})?))?(?:\s+(?<note>.*?))??(?:\s+(?<logged_time>\d{4}[-/.年]\d{1,2}[-/.月]\d{1,2}日?(?:[\sT]\d{1,2}:\d{2}(?::\d{2})?)?))?\s*$";
+
+const NUMBER_PATTERN: &str = r"[0-9０-９一二三四五六七八九十百〇零壱弐参拾]+";
+
+impl BuiltinNotation {
+    pub fn pattern(&self) -> String {
+        match self {
+            BuiltinNotation::SeasonEpisodeCode => format!(
+                r"^(?<name>.+?)\s*(?i:S)(?<season>\d{{1,3}})(?i:E)(?<episode>\d{{1,4}}(?:-\d{{1,4}})?){}",
+                SUFFIX_PATTERN
+            ),
+            BuiltinNotation::JapaneseEpisode => format!(
+                r"^(?<name>.+?)\s*(?:第(?<season>{n})期\s*)?第(?<episode>{n}(?:[-~～]{n})?)[話话]{suffix}",
+                n = NUMBER_PATTERN,
+                suffix = SUFFIX_PATTERN
+            ),
+            BuiltinNotation::EnglishSeason => format!(
+                r"^(?<name>.+?)\s+(?i:Season)\s+(?<season>\d+|[IVXLCⅠ-Ⅻ]+)(?:\s+(?i:Part)\s+(?<part>\d+|[IVXⅠ-Ⅻ]+))?(?:\s+(?i:Episode|Ep\.?)\s*(?<episode>\d+(?:-\d+)?))?{}",
+                SUFFIX_PATTERN
+            ),
+        }
+    }
+}
+
+// Convert the full-width digits, such as `１２`, to the ASCII ones.
+fn normalize_full_width_digit(number_str: &str) -> String {
+    number_str
+        .chars()
+        .map(|c| match c {
+            '０'..='９' => char::from_u32(c as u32 - '０' as u32 + '0' as u32).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+// Convert the Japanese kanji forms, such as `〇` and `弐`, to the Chinese ones.
+fn normalize_kanji(number_str: &str) -> String {
+    number_str
+        .chars()
+        .map(|c| match c {
+            '〇' => '零',
+            '壱' => '一',
+            '弐' => '二',
+            '参' => '三',
+            '拾' => '十',
+            _ => c,
+        })
+        .collect()
+}
+
+// Only the uppercase roman numerals are accepted, so a lowercase letter such as `x` is not a number.
+fn parse_roman(number_str: &str) -> Option<u16> {
+    // The Unicode roman numerals from Ⅰ to Ⅻ are single characters.
+    let mut chars = number_str.chars();
+    if let (Some(c @ 'Ⅰ'..='Ⅻ'), None) = (chars.next(), chars.next()) {
+        return Some((c as u32 - 'Ⅰ' as u32 + 1) as u16);
+    }
+
+    let value_list: Vec<u16> = number_str
+        .chars()
+        .map(|c| match c {
+            'I' => Some(1),
+            'V' => Some(5),
+            'X' => Some(10),
+            'L' => Some(50),
+            'C' => Some(100),
+            'D' => Some(500),
+            'M' => Some(1000),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    if value_list.is_empty() {
+        return None;
+    }
+    // A value smaller than the next one is subtracted, such as the I in IV.
+    let mut number: i32 = 0;
+    for (index, value) in value_list.iter().enumerate() {
+        match value_list.get(index + 1) {
+            Some(next) if next > value => number -= *value as i32,
+            _ => number += *value as i32,
+        }
+    }
+    // Only the canonical form is accepted, so IIII or IC is not a number.
+    (1..=3999)
+        .contains(&number)
+        .then_some(number as u16)
+        .filter(|number| to_roman(*number) == number_str)
+}
+
+fn to_roman(mut number: u16) -> String {
+    const SYMBOL_LIST: [(u16, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    let mut roman = String::new();
+    for (value, symbol) in SYMBOL_LIST {
+        while number >= value {
+            roman.push_str(symbol);
+            number -= value;
+        }
+    }
+    roman
+}
+
+// Parse the number in the ASCII or full-width digits, the Chinese or Japanese kanji forms, or
+// the roman numerals.
+pub fn parse_number(number_str: &str) -> Option<u16> {
+    let number_str = normalize_full_width_digit(number_str.trim());
+    if let Ok(number) = number_str.parse::<u16>() {
+        return Some(number);
+    }
+    from_chinese_to_u16(normalize_kanji(&number_str))
+        .ok()
+        .or_else(|| parse_roman(&number_str))
+}
+
+// Parse the part of a split season, such as `上`/`下`, `前半`/`後半`, `2` or `2nd`.
+pub fn parse_part(part_str: &str) -> Option<u16> {
+    let part_str = part_str.trim();
+    match part_str {
+        "上" | "前" | "前半" | "前編" | "前篇" => return Some(1),
+        "下" | "后" | "後" | "后半" | "後半" | "後編" | "后篇" | "後篇" => {
+            return Some(2);
+        }
+        _ => {}
+    }
+    let number_str = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|suffix| part_str.strip_suffix(suffix))
+        .unwrap_or(part_str);
+    parse_number(number_str)
+}
+
+// Parse the kind of the episode, such as `SP`, `OVA`, `剧场版` or `総集編`.
+pub fn parse_episode_kind(kind_str: &str) -> Option<EpisodeKind> {
+    match kind_str.trim().to_lowercase().as_str() {
+        "" | "正片" | "本篇" | "本編" | "regular" | "episode" | "ep" => {
+            Some(EpisodeKind::Regular)
+        }
+        "sp" | "special" | "特别篇" | "特別篇" | "特別編" | "番外" | "番外篇" | "番外編" => {
+            Some(EpisodeKind::Special)
+        }
+        "ova" | "oad" => Some(EpisodeKind::Ova),
+        "movie" | "film" | "剧场版" | "劇場版" | "电影" | "映画" => {
+            Some(EpisodeKind::Movie)
+        }
+        "recap" | "总集篇" | "總集篇" | "総集編" | "总集编" => Some(EpisodeKind::Recap),
+        _ => None,
+    }
+}
+
+// Parse the decimal episode such as `12.5`, the whole numbers are left to parse_number.
+pub fn parse_episode_decimal(episode_str: &str) -> Option<f32> {
+    let episode_str = episode_str.trim();
+    let (whole, fraction) = episode_str.split_once('.')?;
+    if whole.is_empty()
+        || fraction.is_empty()
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    episode_str.parse::<f32>().ok()
+}
+
+// How the captured rating is read, the rating is always normalized to the 0-10 scale.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct RatingFormat {
+    // The number of stars for the full score when only the filled stars are written, such as `★★★★`.
+    pub star_max: u16,
+    // The full score of a bare number such as `8.5`, the fractions such as `4/5` carry their own.
+    pub number_scale: u16,
+}
+
+impl Default for RatingFormat {
+    fn default() -> Self {
+        RatingFormat {
+            star_max: 5,
+            number_scale: 10,
+        }
+    }
+}
+
+const FILLED_STAR_LIST: &[char] = &['★', '⭐', '🌟'];
+const EMPTY_STAR_LIST: &[char] = &['☆', '✩'];
+
+// Parse the rating such as `8/10`, `4/5`, `85%`, `★★★★☆`, `★★★★`, `4星` or `8.5` into the 0-10
+// scale. The rating above the full score is invalid.
+pub fn parse_rating(rating_str: &str, rating_format: &RatingFormat) -> Option<f32> {
+    let rating_str = rating_str.trim();
+    let parse_score = |score_str: &str| {
+        normalize_full_width_digit(score_str.trim())
+            .parse::<f32>()
+            .ok()
+            .filter(|score| *score >= 0.0)
+    };
+    let (score, full_score) = if let Some((score_str, full_score_str)) = rating_str.split_once('/')
+    {
+        (parse_score(score_str)?, parse_score(full_score_str)?)
+    } else if let Some(score_str) = rating_str.strip_suffix(['%', '％']) {
+        (parse_score(score_str)?, 100.0)
+    } else if !rating_str.is_empty()
+        && rating_str
+            .chars()
+            .all(|c| FILLED_STAR_LIST.contains(&c) || EMPTY_STAR_LIST.contains(&c))
+    {
+        let filled_count = rating_str
+            .chars()
+            .filter(|c| FILLED_STAR_LIST.contains(c))
+            .count();
+        let empty_count = rating_str.chars().count() - filled_count;
+        let full_score = if empty_count > 0 {
+            filled_count + empty_count
+        } else {
+            rating_format.star_max as usize
+        };
+        (filled_count as f32, full_score as f32)
+    } else if let Some(star_str) = rating_str
+        .strip_suffix("星")
+        .or_else(|| rating_str.strip_suffix("stars"))
+        .or_else(|| rating_str.strip_suffix("star"))
+    {
+        (parse_score(star_str)?, rating_format.star_max as f32)
+    } else {
+        let score_str = rating_str.strip_suffix("分").unwrap_or(rating_str);
+        (parse_score(score_str)?, rating_format.number_scale as f32)
+    };
+    (full_score > 0.0 && score <= full_score).then(|| score / full_score * 10.0)
+}
+
+#[cfg(test)]
+mod notation_tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_parse_number_forms() {
+        assert_eq!(parse_number("12"), Some(12));
+        assert_eq!(parse_number("１２"), Some(12));
+        assert_eq!(parse_number("十二"), Some(12));
+        assert_eq!(parse_number("弐拾"), Some(20));
+        assert_eq!(parse_number("II"), Some(2));
+        assert_eq!(parse_number("XIV"), Some(14));
+        assert_eq!(parse_number("IV"), Some(4));
+        assert_eq!(parse_number("IX"), Some(9));
+        assert_eq!(parse_number("XL"), Some(40));
+        assert_eq!(parse_number("XC"), Some(90));
+        assert_eq!(parse_number("MCMXCIV"), Some(1994));
+        assert_eq!(parse_number("IIII"), None);
+        assert_eq!(parse_number("IC"), None);
+        assert_eq!(parse_number("x"), None);
+        assert_eq!(parse_number("Ⅲ"), Some(3));
+        assert_eq!(parse_number("ABC"), None);
+    }
+
+    #[test]
+    fn test_parse_part() {
+        assert_eq!(parse_part("上"), Some(1));
+        assert_eq!(parse_part("下"), Some(2));
+        assert_eq!(parse_part("後半"), Some(2));
+        assert_eq!(parse_part("2"), Some(2));
+        assert_eq!(parse_part("2nd"), Some(2));
+        assert_eq!(parse_part("II"), Some(2));
+        assert_eq!(parse_part("last"), None);
+    }
+
+    #[test]
+    fn test_parse_episode_kind() {
+        assert_eq!(parse_episode_kind("SP"), Some(EpisodeKind::Special));
+        assert_eq!(parse_episode_kind("OAD"), Some(EpisodeKind::Ova));
+        assert_eq!(parse_episode_kind("劇場版"), Some(EpisodeKind::Movie));
+        assert_eq!(parse_episode_kind("総集編"), Some(EpisodeKind::Recap));
+        assert_eq!(parse_episode_kind("bonus"), None);
+        assert_eq!(parse_episode_decimal("12.5"), Some(12.5));
+        assert_eq!(parse_episode_decimal("12"), None);
+        assert_eq!(parse_episode_decimal("1.2.3"), None);
+    }
+
+    #[test]
+    fn test_parse_rating() {
+        let rating_format = RatingFormat::default();
+        let rating = |rating_str| parse_rating(rating_str, &rating_format);
+        assert_eq!(rating("8/10"), Some(8.0));
+        assert_eq!(rating("4/5"), Some(8.0));
+        assert_eq!(rating("85%"), Some(8.5));
+        assert_eq!(rating("★★★★☆"), Some(8.0));
+        assert_eq!(rating("★★★★"), Some(8.0));
+        assert_eq!(rating("★★★☆☆☆"), Some(5.0));
+        assert_eq!(rating("4星"), Some(8.0));
+        assert_eq!(rating("8.5"), Some(8.5));
+        assert_eq!(rating("11/10"), None);
+        assert_eq!(rating("good"), None);
+
+        let rating_format = RatingFormat {
+            star_max: 10,
+            number_scale: 100,
+        };
+        assert_eq!(parse_rating("★★★★", &rating_format), Some(4.0));
+        assert_eq!(parse_rating("85", &rating_format), Some(8.5));
+    }
+
+    #[test]
+    fn test_builtin_notation_pattern() {
+        let captures = |notation: BuiltinNotation, line: &str| {
+            let re = Regex::new(&notation.pattern()).unwrap();
+            let caps = re.captures(line).unwrap();
+            [
+                "name",
+                "season",
+                "episode",
+                "time_at_episode",
+                "note",
+                "logged_time",
+            ]
+            .map(|group| caps.name(group).map(|m| m.as_str().to_string()))
+        };
+
+        assert_eq!(
+            captures(
+                BuiltinNotation::SeasonEpisodeCode,
+                "The Show S02E05 12:30 2023-10-01 21:00"
+            ),
+            [
+                Some(String::from("The Show")),
+                Some(String::from("02")),
+                Some(String::from("05")),
+                Some(String::from("12:30")),
+                None,
+                Some(String::from("2023-10-01 21:00")),
+            ]
+        );
+        assert_eq!(
+            captures(BuiltinNotation::JapaneseEpisode, "番組 第2期 第十二話 看完"),
+            [
+                Some(String::from("番組")),
+                Some(String::from("2")),
+                Some(String::from("十二")),
+                None,
+                Some(String::from("看完")),
+                None,
+            ]
+        );
+        assert_eq!(
+            captures(BuiltinNotation::EnglishSeason, "The Show Season II Part 2"),
+            [
+                Some(String::from("The Show")),
+                Some(String::from("II")),
+                None,
+                None,
+                None,
+                None,
+            ]
+        );
+    }
+}
@@ -1,41 +1,58 @@
-use super::cache_db::Cache;
-use super::datatype::Metadata;
+use super::cache_db::{Cache, RunStats};
+use super::datatype::*;
+use super::datetime::*;
+use super::notation::{
+    parse_episode_decimal, parse_episode_kind, parse_number, parse_part, parse_rating,
+};
 use super::parser_task_manager::*;
+use super::pattern::{METADATA_GROUP_LIST, PatternPool};
 use super::{log_debug, log_error};
-use chinese_number::from_chinese_to_u16;
-use chrono::{NaiveDateTime, NaiveTime};
-use regex::Regex;
-use xxhash_rust::xxh3;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
 
-type DefaultParserCallback = fn(&str, u32, u64, &Vec<String>, &Vec<String>) -> Option<Metadata>;
+type DefaultParserCallback = fn(&str, usize, u64, &PatternPool) -> ParsedLine;
 
 pub struct Parser {
-    // reg pattern should follow:
+    // The compiled reg pattern pool, the reg pattern should follow:
     //  - supoport named group
     //  - group name follows the Metadata structutre's field
     //  - sub set should have higher priority
-    pub reg_pattern_list: Vec<String>,
-    // This is another pattern, it will run this against each line to determine if this entry
-    // represent the watching is finished or not.
-    pub finished_reg_pattern_list: Vec<String>,
+    // The finished reg pattern is run against each line to determine if this entry represent
+    // the watching is finished or not.
+    pub pattern_pool: PatternPool,
 
     pub cache: Option<Cache>,
 
+    // The watching log the lines are read from, kept in the provenance of each entry.
+    pub source_path: Option<Arc<Path>>,
+
+    // Report the identical lines logged right after each other or at the same time.
+    pub b_flag_duplicates: bool,
+
+    // The reference for resolving the relative logged_time before any entry has a logged_time.
+    pub file_modified_time: Option<NaiveDateTime>,
+
     task_manager: ParserTaskManager<DefaultParserCallback>,
 }
 
-fn parse_number(number_str: &str) -> Option<u16> {
-    match number_str.parse::<u16>() {
-        Ok(number) => Some(number),
-        Err(_) => from_chinese_to_u16(number_str).ok(),
-    }
-}
+const RANGE_SEPARATOR_LIST: &[char] = &['-', '~', '～', '—', '–', '至', '到'];
 
-fn parse_datetime(datetime_str: &str) -> Option<NaiveDateTime> {
-    NaiveDateTime::parse_from_str(datetime_str, "%Y-%m-%d %H:%M").ok()
+// Parse a single number or a range such as `3-5` or `三至五` into (start, end).
+pub fn parse_number_range(range_str: &str) -> Option<(u16, u16)> {
+    match range_str.split_once(RANGE_SEPARATOR_LIST) {
+        Some((start_str, end_str)) => {
+            let start = parse_number(start_str.trim())?;
+            let end = parse_number(end_str.trim())?;
+            (start <= end).then_some((start, end))
+        }
+        None => parse_number(range_str).map(|number| (number, number)),
+    }
 }
 
-fn parse_time(time_str: &str) -> Option<NaiveTime> {
+pub fn parse_time(time_str: &str) -> Option<NaiveTime> {
     let parts: Vec<&str> = time_str.split(":").collect();
     if parts.len() == 3 {
         let hours = parse_number(parts[0]);
@@ -54,151 +71,490 @@ fn parse_time(time_str: &str) -> Option<NaiveTime> {
     None
 }
 
+// The time at the episode is logged as `mm:ss` or `hh:mm:ss`.
+pub fn format_time_at_episode(time_at_episode: NaiveTime) -> String {
+    if time_at_episode.hour() == 0 {
+        time_at_episode.format("%M:%S").to_string()
+    } else {
+        time_at_episode.format("%H:%M:%S").to_string()
+    }
+}
+
+// Parse the captured text of the group, the failure is recorded in the diagnostic list.
+fn parse_capture<T>(
+    caps: &Captures,
+    group: &str,
+    parse_fn: impl Fn(&str) -> Option<T>,
+    invalid_reason: fn(String, String) -> DiagnosticReason,
+    diagnostic_list: &mut Vec<DiagnosticReason>,
+) -> Option<T> {
+    let text = caps.name(group)?.as_str();
+    let value = parse_fn(text);
+    if value.is_none() {
+        diagnostic_list.push(invalid_reason(group.to_string(), text.to_string()));
+    }
+    value
+}
+
+fn invalid_number(group: String, text: String) -> DiagnosticReason {
+    DiagnosticReason::InvalidNumber { group, text }
+}
+
+fn invalid_time(group: String, text: String) -> DiagnosticReason {
+    DiagnosticReason::InvalidTime { group, text }
+}
+
+// The episode is either captured by `episode` as a single number, a range or a decimal
+// number, or by `episode_start` and `episode_end`. The end is only kept for a range.
+fn parse_episode(
+    caps: &Captures,
+    diagnostic_list: &mut Vec<DiagnosticReason>,
+) -> (Option<u16>, Option<u16>, Option<f32>) {
+    if let Some(episode_decimal) = caps
+        .name("episode")
+        .and_then(|m| parse_episode_decimal(m.as_str()))
+    {
+        return (None, None, Some(episode_decimal));
+    }
+    let (start, end) = if caps.name("episode_start").is_some() {
+        let start = parse_capture(
+            caps,
+            "episode_start",
+            parse_number,
+            invalid_number,
+            diagnostic_list,
+        );
+        let end = parse_capture(
+            caps,
+            "episode_end",
+            parse_number,
+            invalid_number,
+            diagnostic_list,
+        );
+        if let (Some(start), Some(end)) = (start, end)
+            && start > end
+        {
+            diagnostic_list.push(invalid_number(
+                String::from("episode_end"),
+                caps["episode_end"].to_string(),
+            ));
+            return (Some(start), None, None);
+        }
+        (start, end)
+    } else {
+        match parse_capture(
+            caps,
+            "episode",
+            parse_number_range,
+            invalid_number,
+            diagnostic_list,
+        ) {
+            Some((start, end)) => (Some(start), Some(end)),
+            None => (None, None),
+        }
+    };
+    (start, end.filter(|end| Some(*end) != start), None)
+}
+
+// The date of the header is captured by the `logged_time` group, or the whole match without it.
+fn parse_date_header(line: &str, re: &Regex, pattern_pool: &PatternPool) -> ParsedLine {
+    let Some(caps) = re.captures(line) else {
+        return ParsedLine::failed(DiagnosticReason::NoMatch);
+    };
+    let text = caps
+        .name("logged_time")
+        .unwrap_or_else(|| caps.get(0).unwrap())
+        .as_str()
+        .trim();
+    match parse_datetime(text, &pattern_pool.logged_time_format_list) {
+        Some(datetime) => ParsedLine {
+            metadata: None,
+            date_header: Some(datetime.date()),
+            diagnostic_list: vec![],
+        },
+        None => ParsedLine::failed(DiagnosticReason::InvalidDateTime {
+            text: text.to_string(),
+        }),
+    }
+}
+
+// The line number is 1-based, the source path of the provenance is filled by the Parser.
+pub fn parse_line(
+    line: &str,
+    line_number: usize,
+    hash_value: u64,
+    pattern_pool: &PatternPool,
+) -> ParsedLine {
+    let mut diagnostic_list = Vec::new();
+
+    // The date header has a higher priority, otherwise it may become a bogus entry.
+    if let Some((_, re)) = pattern_pool.date_header_pattern_list.first_match(line) {
+        return parse_date_header(line, re, pattern_pool);
+    }
+
+    let Some((reg_index, caps)) = pattern_pool
+        .reg_pattern_list
+        .first_match(line)
+        .and_then(|(reg_index, re)| re.captures(line).map(|caps| (reg_index, caps)))
+    else {
+        return ParsedLine::failed(DiagnosticReason::NoMatch);
+    };
+    let reg = &pattern_pool.reg_pattern_list.pattern_list[reg_index];
+    let Some(name) = caps
+        .name("name")
+        .map(|m| String::from(m.as_str()))
+        .filter(|name| !name.is_empty())
+    else {
+        return ParsedLine::failed(DiagnosticReason::BadCapture {
+            group: String::from("name"),
+        });
+    };
+
+    let (status, matched_status_reg_pattern) = pattern_pool
+        .status_reg_pattern_list()
+        .into_iter()
+        .find_map(|(status, _, status_reg_pattern_list)| {
+            status_reg_pattern_list
+                .first_match(line)
+                .map(|(status_index, _)| {
+                    (
+                        status,
+                        Some(status_reg_pattern_list.pattern_list[status_index].clone()),
+                    )
+                })
+        })
+        .unwrap_or((WatchStatus::Watching, None));
+
+    let (episode, episode_end, episode_decimal) = parse_episode(&caps, &mut diagnostic_list);
+    let kind = match caps.name("kind") {
+        Some(m) => parse_episode_kind(m.as_str()).unwrap_or_else(|| {
+            diagnostic_list.push(DiagnosticReason::UnknownEpisodeKind {
+                text: m.as_str().to_string(),
+            });
+            EpisodeKind::Regular
+        }),
+        None => EpisodeKind::Regular,
+    };
+    let time_at_episode: Option<NaiveTime> = parse_capture(
+        &caps,
+        "time_at_episode",
+        parse_time,
+        invalid_time,
+        &mut diagnostic_list,
+    );
+    let season: Option<u16> = parse_capture(
+        &caps,
+        "season",
+        parse_number,
+        invalid_number,
+        &mut diagnostic_list,
+    );
+    let part: Option<u16> = parse_capture(
+        &caps,
+        "part",
+        parse_part,
+        invalid_number,
+        &mut diagnostic_list,
+    );
+    // The logged_time which cannot be parsed is kept as text, it may be a relative one resolved
+    // with the previous entries later.
+    let logged_time_text: Option<String> = caps
+        .name("logged_time")
+        .map(|m| String::from(m.as_str().trim()));
+    let logged_time: Option<NaiveDateTime> = logged_time_text
+        .as_ref()
+        .and_then(|text| parse_datetime(text, &pattern_pool.logged_time_format_list));
+    let note: Option<String> = caps.name("note").map(|m| String::from(m.as_str()));
+    let rating: Option<f32> = parse_capture(
+        &caps,
+        "rating",
+        |text| parse_rating(text, &pattern_pool.rating_format),
+        invalid_number,
+        &mut diagnostic_list,
+    );
+    let re = pattern_pool.reg_pattern_list.regex(reg_index);
+    let extra_field_map: BTreeMap<String, String> = re
+        .capture_names()
+        .flatten()
+        .filter(|group| !METADATA_GROUP_LIST.contains(group))
+        .filter_map(|group| {
+            caps.name(group)
+                .map(|m| (group.to_string(), m.as_str().trim().to_string()))
+        })
+        .collect();
+    log_debug!(
+        "hash_value: {}, name: {}, status: {}, season: {:?}, episode: {:?}, time_at_episode: {:?}, logged_time: {:?}, note: {:?}, raw: {}, reg: {}",
+        hash_value,
+        name,
+        status,
+        season,
+        episode,
+        time_at_episode,
+        logged_time,
+        note,
+        line,
+        reg
+    );
+    ParsedLine {
+        metadata: Some(Metadata {
+            provenance: Provenance {
+                source_path: None,
+                line_number,
+            },
+            id: hash_value,
+            occurrence: 0,
+            name,
+            status,
+            episode,
+            episode_end,
+            episode_decimal,
+            kind,
+            time_at_episode,
+            season,
+            part,
+            logged_time,
+            logged_time_text,
+            b_logged_time_inferred: false,
+            note,
+            rating,
+            extra_field_map,
+            raw_line: line.to_string(),
+            reg_pattern_matched: reg.clone(),
+            status_reg_pattern_matched: matched_status_reg_pattern,
+        }),
+        date_header: None,
+        diagnostic_list,
+    }
+}
+
 impl Parser {
     pub fn new(
-        reg_pattern_list: Vec<String>,
-        finished_reg_pattern_list: Vec<String>,
+        pattern_pool: PatternPool,
         cache: Option<Cache>,
         max_thread_num: usize,
         min_task_per_thread: usize,
     ) -> Self {
         Parser {
-            reg_pattern_list,
-            finished_reg_pattern_list,
+            pattern_pool,
             cache,
+            source_path: None,
+            b_flag_duplicates: false,
+            file_modified_time: None,
             task_manager: ParserTaskManager::new(max_thread_num, min_task_per_thread),
         }
     }
 
-    pub fn parse_metadata(&mut self, lines: &Vec<String>) -> Vec<Metadata> {
-        let mut result = Vec::<Metadata>::new();
+    fn provenance(&self, line_number: usize) -> Provenance {
+        Provenance {
+            source_path: self.source_path.clone(),
+            line_number,
+        }
+    }
+
+    pub fn parse_metadata(&mut self, lines: &[String]) -> ParseResult {
+        let mut entry_list = Vec::<Metadata>::new();
+        let mut diagnostic_list = Vec::<LineDiagnostic>::new();
+        // The date headers with their 1-based line numbers, they are not cached.
+        let mut date_header_list = Vec::<(usize, NaiveDate)>::new();
+        let mut cache_hit_count = 0;
 
-        let reg_pool_string = [
-            &self.reg_pattern_list[..],
-            &self.finished_reg_pattern_list[..],
-        ]
-        .concat()
-        .join("###")
-        .to_string();
-
-        let mut index: u32 = 0;
-        for line in lines {
-            let hash_value = xxh3::xxh3_64((line.clone() + reg_pool_string.as_str()).as_bytes());
-            let metadata = if let Some(cache) = &self.cache {
-                cache.query_cache(hash_value)
-            } else {
-                None
-            };
-            if let Some(mut m) = metadata {
-                m.index = index;
-                result.push(m);
+        let hash_value_list: Vec<u64> = lines
+            .iter()
+            .map(|line| self.pattern_pool.line_hash(line))
+            .collect();
+        let cached_metadata_map = match &self.cache {
+            Some(cache) => cache.query_cache_map(&hash_value_list).unwrap_or_else(|e| {
+                log_error!("{}", e);
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+        for (line_index, (line, hash_value)) in lines.iter().zip(hash_value_list).enumerate() {
+            let line_number = line_index + 1;
+            // The cached entry comes without a provenance, it always gets the current position.
+            if let Some(metadata) = cached_metadata_map.get(&hash_value) {
+                let mut m = metadata.clone();
+                m.provenance = self.provenance(line_number);
+                entry_list.push(m);
+                cache_hit_count += 1;
                 continue;
             }
 
             self.task_manager.add_task(ParserTask {
-                index,
+                line_number,
                 hash_value,
                 line: line.clone(),
-                reg_pattern_list: self.reg_pattern_list.clone(),
-                finished_reg_pattern_list: self.finished_reg_pattern_list.clone(),
-                callback: move |line,
-                index,
-                hash_value,
-                reg_pattern_list,
-                finished_reg_pattern_list| {
-                    for reg in reg_pattern_list {
-                        let re = Regex::new(reg).unwrap();
-                        if re.is_match(line) {
-                            if let Some(caps) = re.captures(line) {
-                                let name = String::from(caps.name("name").unwrap().as_str());
-
-                                // if name.contains("") {
-                                //     println!("{}", reg);
-                                // }
-
-                                let mut b_finished = false;
-                                let mut matched_finished_reg_pattern: Option<String> = None;
-                                for finished_reg_pattern in finished_reg_pattern_list {
-                                    let finished_re = Regex::new(finished_reg_pattern).unwrap();
-                                    if finished_re.is_match(line) {
-                                        b_finished = true;
-                                        matched_finished_reg_pattern = Some(finished_reg_pattern.to_string().clone());
-                                        break;
-                                    }
-                                }
-
-                                let episode: Option<u16> =
-                                caps.name("episode").and_then(|s| parse_number(s.as_str()));
-                                let time_at_episode: Option<NaiveTime> = caps
-                                    .name("time_at_episode")
-                                    .and_then(|s| parse_time(s.as_str()));
-                                let season: Option<u16> =
-                                caps.name("season").and_then(|s| parse_number(s.as_str()));
-                                let logged_time: Option<NaiveDateTime> = caps
-                                    .name("logged_time")
-                                    .and_then(|s| parse_datetime(s.as_str()));
-                                let note: Option<String> =
-                                caps.name("note").map(|m| String::from(m.as_str()));
-                                log_debug!(
-                                    "hash_value: {}, name: {}, b_finished: {}, season: {:?}, episode: {:?}, time_at_episode: {:?}, logged_time: {:?}, note: {:?}, raw: {}, reg: {}",
-                                    hash_value,
-                                    name,
-                                    b_finished.to_string(),
-                                    season,
-                                    episode,
-                                    time_at_episode,
-                                    logged_time,
-                                    note,
-                                    line,
-                                    reg
-                                );
-                                return Some(Metadata {
-                                    index,
-                                    id: hash_value,
-                                    name,
-                                    b_finished,
-                                    episode,
-                                    time_at_episode,
-                                    season,
-                                    logged_time,
-                                    note,
-                                    raw_line: line.to_string().clone(),
-                                    reg_pattern_matched: reg.to_string().clone(),
-                                    finished_reg_pattern_matched: matched_finished_reg_pattern,
-                                });
-                            }
-                        }
-                    }
-
-                    log_error! {"This line cannot match any regex patterns:\n{}", line};
-
-                    None
-                },
+                callback: parse_line,
             });
-
-            index += 1;
+        }
+        let task_line_number_list: Vec<usize> = self.task_manager.line_number_list();
+        if let Some(cache) = &self.cache
+            && let Err(e) = cache.record_run(&RunStats {
+                hit_count: cache_hit_count,
+                line_count: lines.len(),
+            })
+        {
+            log_error!("{}", e);
         }
 
-        match self.task_manager.run() {
+        log_debug!(
+            "Parsing {} uncached lines with {} threads",
+            self.task_manager.get_task_count(),
+            self.task_manager.get_thread_count()
+        );
+        // The indices in entry_list of the newly parsed entries to be cached.
+        let mut new_entry_index_list = Vec::<usize>::new();
+        match self.task_manager.run(&self.pattern_pool) {
             Ok(result_list) => {
-                for metadata in result_list.into_iter().flatten() {
-                    if let Some(cache) = &self.cache {
-                        match cache.add_cache(&metadata) {
-                            Ok(_r) => {}
-                            Err(e) => {
-                                log_error!("{}", e);
-                            }
-                        }
+                for (parsed_line, line_number) in result_list.into_iter().zip(task_line_number_list)
+                {
+                    let raw_line = &lines[line_number - 1];
+                    for reason in parsed_line.diagnostic_list.iter() {
+                        diagnostic_list.push(LineDiagnostic {
+                            provenance: self.provenance(line_number),
+                            raw_line: raw_line.clone(),
+                            reason: reason.clone(),
+                        });
                     }
-                    result.push(metadata);
+                    if let Some(date_header) = parsed_line.date_header {
+                        date_header_list.push((line_number, date_header));
+                    }
+                    let Some(mut metadata) = parsed_line.metadata else {
+                        continue;
+                    };
+                    metadata.provenance = self.provenance(line_number);
+                    // Only the cleanly parsed lines are cached, so the diagnostics are reported
+                    // again on the next run.
+                    if parsed_line.diagnostic_list.is_empty() {
+                        new_entry_index_list.push(entry_list.len());
+                    }
+                    entry_list.push(metadata);
                 }
             }
             Err(e) => {
                 log_error!("{}", e);
             }
         }
+        if let Some(cache) = &self.cache
+            && let Err(e) =
+                cache.add_cache_list(new_entry_index_list.iter().map(|&index| &entry_list[index]))
+        {
+            log_error!("{}", e);
+        }
+
+        entry_list.sort_by(|a, b| a.provenance.cmp(&b.provenance));
+        self.resolve_relative_logged_time(&mut entry_list, &date_header_list, &mut diagnostic_list);
+        self.count_occurrence(&mut entry_list, &date_header_list, &mut diagnostic_list);
+        diagnostic_list.sort_by(|a, b| a.provenance.cmp(&b.provenance));
+
+        ParseResult {
+            metadata_list: entry_list,
+            diagnostic_list,
+        }
+    }
 
-        result
+    // The identical lines share the content hash, each of them is a separate watch event told
+    // apart by its occurrence. Such a line right after its previous occurrence, with no date
+    // header between them, or logged at the same time, is suspected to be logged twice by
+    // accident. It runs after the logged_time is resolved, so the inferred times are compared too.
+    fn count_occurrence(
+        &self,
+        entry_list: &mut [Metadata],
+        date_header_list: &[(usize, NaiveDate)],
+        diagnostic_list: &mut Vec<LineDiagnostic>,
+    ) {
+        // The occurrence count, the first line number and the latest logged_time of each id.
+        let mut occurrence_map = HashMap::<u64, (u32, usize, Option<NaiveDateTime>)>::new();
+        let mut previous_id: Option<u64> = None;
+        let mut date_header_iter = date_header_list.iter().peekable();
+        for metadata in entry_list.iter_mut() {
+            let line_number = metadata.provenance.line_number;
+            while date_header_iter
+                .next_if(|(header_line_number, _)| *header_line_number < line_number)
+                .is_some()
+            {
+                previous_id = None;
+            }
+            if let Some((count, first_line_number, previous_logged_time)) =
+                occurrence_map.get_mut(&metadata.id)
+            {
+                metadata.occurrence = *count;
+                *count += 1;
+                let b_same_time =
+                    metadata.logged_time.is_some() && metadata.logged_time == *previous_logged_time;
+                if self.b_flag_duplicates && (previous_id == Some(metadata.id) || b_same_time) {
+                    diagnostic_list.push(LineDiagnostic {
+                        provenance: metadata.provenance.clone(),
+                        raw_line: metadata.raw_line.clone(),
+                        reason: DiagnosticReason::SuspectedDuplicate {
+                            first_line_number: *first_line_number,
+                        },
+                    });
+                }
+                *previous_logged_time = metadata.logged_time;
+            } else {
+                occurrence_map.insert(metadata.id, (1, line_number, metadata.logged_time));
+            }
+            previous_id = Some(metadata.id);
+        }
+    }
+
+    // The relative logged_time depends on the entries before it, so it is resolved after parsing
+    // in the line order, against the previous entry's logged_time, or the file modified time
+    // if there is no previous one. A date header acts like an entry logged at the start of the
+    // day, and the following entries without a logged_time group are logged on that day.
+    fn resolve_relative_logged_time(
+        &self,
+        entry_list: &mut [Metadata],
+        date_header_list: &[(usize, NaiveDate)],
+        diagnostic_list: &mut Vec<LineDiagnostic>,
+    ) {
+        let mut previous_logged_time: Option<NaiveDateTime> = None;
+        let mut current_date: Option<NaiveDate> = None;
+        let mut date_header_iter = date_header_list.iter().peekable();
+        for metadata in entry_list.iter_mut() {
+            let line_number = metadata.provenance.line_number;
+            while let Some((_, date_header)) = date_header_iter
+                .next_if(|(header_line_number, _)| *header_line_number < line_number)
+            {
+                current_date = Some(*date_header);
+                previous_logged_time = Some(date_header.and_time(NaiveTime::MIN));
+            }
+
+            match (&metadata.logged_time, &metadata.logged_time_text) {
+                (Some(_), _) => {}
+                (None, None) => {
+                    if let Some(date) = current_date {
+                        metadata.logged_time = Some(date.and_time(NaiveTime::MIN));
+                        metadata.b_logged_time_inferred = true;
+                    }
+                }
+                (None, Some(text)) => {
+                    let reference = match (previous_logged_time, self.file_modified_time) {
+                        (Some(previous), _) => Some((previous, ReferenceKind::PreviousEntry)),
+                        (None, Some(file_modified)) => {
+                            Some((file_modified, ReferenceKind::FileModified))
+                        }
+                        (None, None) => None,
+                    };
+                    metadata.logged_time = reference.and_then(|(reference, reference_kind)| {
+                        resolve_relative_datetime(text, reference, reference_kind)
+                    });
+                    if metadata.logged_time.is_some() {
+                        metadata.b_logged_time_inferred = true;
+                    } else {
+                        diagnostic_list.push(LineDiagnostic {
+                            provenance: metadata.provenance.clone(),
+                            raw_line: metadata.raw_line.clone(),
+                            reason: DiagnosticReason::InvalidDateTime { text: text.clone() },
+                        });
+                    }
+                }
+            }
+            if metadata.logged_time.is_some() {
+                previous_logged_time = metadata.logged_time;
+            }
+        }
     }
 }
 
@@ -207,18 +563,454 @@ mod parser_tests {
     use super::*;
     use std::fs;
 
+    // The patterns from the README.
+    fn standard_pattern_pool() -> PatternPool {
+        PatternPool::new(
+            [
+                r"(?<name>.+)第(?<season>[0-9一二三四五六七八九十零百千]+)季\s(?<kind>SP)\s(?<time_at_episode>\d{1,2}:\d{1,2}.*)\s(?<logged_time>\d{4}-\d{2}-\d{2}\s.*)$",
+                r"(?<name>.+)第(?<season>[0-9一二三四五六七八九十零百千]+)季\s第(?<episode>[0-9一二三四五六七八九十零百千]+)集\s(?<time_at_episode>\d{1,2}:\d{1,2}.*)\s(?<logged_time>\d{4}-\d{2}-\d{2}\s.*)$",
+                r"(?<name>.+)第(?<season>[0-9一二三四五六七八九十零百千]+)季(?<episode>\d+)\s看完\s(?<logged_time>\d{4}-\d{2}-\d{2}\s.*)$",
+                r"(?<name>.+)第(?<season>[0-9一二三四五六七八九十零百千]+)季\s看完\s(?<logged_time>\d{4}-\d{2}-\d{2}\s.*)$",
+                r"(?<name>.+)第(?<season>[0-9一二三四五六七八九十零百千]+)季\s看完$",
+                r"(?<name>.+)\sSeason\s(?<season>\d+)\s看完\s(?<logged_time>\d{4}-\d{2}-\d{2}\s.*)$",
+                r"(?<name>.+)\sSeason\s(?<season>\d+)\s看完$",
+            ]
+            .map(String::from)
+            .to_vec(),
+            [r"[^\d\s]\s看完\s", r"\s看完$", r"\sSeason\s\d+\s看完\s"]
+                .map(String::from)
+                .to_vec(),
+        )
+        .unwrap()
+    }
+
     #[test]
     fn test_standard_parsing() {
         let file_path = "tests/standard.txt";
         let contents = fs::read_to_string(file_path).unwrap();
 
-        let mut parser = Parser::new(Vec::<String>::new(), Vec::<String>::new(), None, 1, 1);
+        let mut parser = Parser::new(standard_pattern_pool(), None, 1, 1);
 
         let lines: Vec<String> = contents.lines().map(String::from).collect();
 
-        let metadata_list = parser.parse_metadata(&lines);
+        let parse_result = parser.parse_metadata(&lines);
 
         // println!("File constents:\n{}", contents);
-        assert_eq! {lines.len(), metadata_list.len()};
+        assert_eq! {lines.len(), parse_result.metadata_list.len()};
+        assert!(parse_result.diagnostic_list.is_empty());
+    }
+
+    #[test]
+    fn test_cached_entries_keep_provenance() {
+        let pattern_pool = PatternPool::new(
+            vec![String::from(r"^(?<name>\S+)\s第(?<episode>\d+)集$")],
+            vec![],
+        )
+        .unwrap();
+        let cache = Cache::new(":memory:").unwrap();
+        let mut parser = Parser::new(pattern_pool, Some(cache), 1, 1);
+        parser.source_path = Some(Arc::from(Path::new("log.txt")));
+
+        parser.parse_metadata(&[String::from("BBB 第1集")]);
+        let lines = ["AAA 第1集", "BBB 第1集", "CCC 第1集"].map(String::from);
+        let parse_result = parser.parse_metadata(&lines);
+        let entry_list: Vec<(String, String)> = parse_result
+            .metadata_list
+            .iter()
+            .map(|metadata| (metadata.name.clone(), metadata.provenance.to_string()))
+            .collect();
+        assert_eq!(
+            entry_list,
+            vec![
+                (String::from("AAA"), String::from("log.txt:1")),
+                (String::from("BBB"), String::from("log.txt:2")),
+                (String::from("CCC"), String::from("log.txt:3")),
+            ]
+        );
+        let run_stats = parser.cache.as_ref().unwrap().last_run().unwrap().unwrap();
+        assert_eq!((run_stats.hit_count, run_stats.line_count), (1, 3));
+    }
+
+    #[test]
+    fn test_duplicate_lines() {
+        let pattern_pool = PatternPool::new(
+            vec![String::from(
+                r"^(?<name>\S+)\s第(?<episode>\d+)集(?:\s(?<logged_time>\d{4}-\d{2}-\d{2}))?$",
+            )],
+            vec![],
+        )
+        .unwrap();
+        let cache = Cache::new(":memory:").unwrap();
+        let mut parser = Parser::new(pattern_pool, Some(cache), 1, 1);
+        parser.b_flag_duplicates = true;
+        let lines = [
+            "ABC 第1集",
+            "ABC 第2集",
+            "ABC 第1集",
+            "ABC 第1集",
+            "ABC 第3集 2023-10-01",
+            "ABC 第2集",
+            "ABC 第3集 2023-10-01",
+        ]
+        .map(String::from);
+
+        for _ in 0..2 {
+            let parse_result = parser.parse_metadata(&lines);
+            let occurrence_list: Vec<u32> = parse_result
+                .metadata_list
+                .iter()
+                .map(|metadata| metadata.occurrence)
+                .collect();
+            assert_eq!(occurrence_list, vec![0, 0, 1, 2, 0, 1, 1]);
+            let duplicate_list: Vec<(usize, DiagnosticReason)> = parse_result
+                .diagnostic_list
+                .into_iter()
+                .map(|d| (d.provenance.line_number, d.reason))
+                .collect();
+            assert_eq!(
+                duplicate_list,
+                vec![
+                    (
+                        4,
+                        DiagnosticReason::SuspectedDuplicate {
+                            first_line_number: 1
+                        }
+                    ),
+                    (
+                        7,
+                        DiagnosticReason::SuspectedDuplicate {
+                            first_line_number: 5
+                        }
+                    ),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_duplicate_lines_under_date_headers() {
+        let pattern_pool = PatternPool::new(
+            vec![String::from(
+                r"^(?<name>\S+)\s第(?<season>\d+)季\s第(?<episode>\d+)集\s(?<time_at_episode>\d{1,2}:\d{2})$",
+            )],
+            vec![],
+        )
+        .unwrap()
+        .with_date_header_pattern_list(vec![String::from(r"^\d{4}-\d{2}-\d{2}$")])
+        .unwrap();
+        let mut parser = Parser::new(pattern_pool, None, 1, 1);
+        parser.b_flag_duplicates = true;
+        let lines = [
+            "2024-03-02",
+            "ABC 第1季 第2集 12:30",
+            "2024-03-03",
+            "ABC 第1季 第2集 12:30",
+            "ABC 第1季 第2集 12:30",
+        ]
+        .map(String::from);
+
+        let parse_result = parser.parse_metadata(&lines);
+        let duplicate_list: Vec<(usize, DiagnosticReason)> = parse_result
+            .diagnostic_list
+            .into_iter()
+            .map(|d| (d.provenance.line_number, d.reason))
+            .collect();
+        assert_eq!(
+            duplicate_list,
+            vec![(
+                5,
+                DiagnosticReason::SuspectedDuplicate {
+                    first_line_number: 2
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_line_fields() {
+        let pattern_pool = standard_pattern_pool();
+
+        let metadata = parse_line(
+            "ABC 第二季 第十二集 01:02:03 2023-10-05 10:00",
+            3,
+            42,
+            &pattern_pool,
+        )
+        .metadata
+        .unwrap();
+        assert_eq!(metadata.name, "ABC ");
+        assert_eq!(metadata.season, Some(2));
+        assert_eq!(metadata.episode, Some(12));
+        assert_eq!(metadata.time_at_episode, NaiveTime::from_hms_opt(1, 2, 3));
+        assert!(metadata.logged_time.is_some());
+        assert_eq!(metadata.status, WatchStatus::Watching);
+        assert_eq!(
+            metadata.reg_pattern_matched,
+            pattern_pool.reg_pattern_list.pattern_list[1]
+        );
+
+        let metadata = parse_line("DEF Season 2 看完 2023-10-06 10:00", 5, 43, &pattern_pool)
+            .metadata
+            .unwrap();
+        assert_eq!(metadata.status, WatchStatus::Finished);
+        assert_eq!(metadata.season, Some(2));
+
+        let metadata = parse_line("ABC 第二季 SP 05:00 2023-10-04 10:00", 4, 45, &pattern_pool)
+            .metadata
+            .unwrap();
+        assert_eq!(metadata.kind, EpisodeKind::Special);
+        assert_eq!(metadata.episode, None);
+
+        let parsed_line = parse_line("not a watching entry", 6, 44, &pattern_pool);
+        assert!(parsed_line.metadata.is_none());
+        assert_eq!(parsed_line.diagnostic_list, vec![DiagnosticReason::NoMatch]);
+    }
+
+    #[test]
+    fn test_parse_episode_range() {
+        let pattern_pool = PatternPool::new(
+            vec![
+                String::from(r"^(?<name>\S+)\s第(?<episode_start>\d+)集到第(?<episode_end>\d+)集$"),
+                String::from(r"^(?<name>\S+)\s第(?<episode>\S+)集$"),
+            ],
+            vec![],
+        )
+        .unwrap();
+        let episode_list: Vec<(Option<u16>, Option<u16>, usize)> = [
+            "ABC 第3-5集",
+            "ABC 第三至五集",
+            "ABC 第7集",
+            "ABC 第2集到第4集",
+            "ABC 第5~3集",
+            "ABC 第12.5集",
+        ]
+        .iter()
+        .map(|line| {
+            let parsed_line = parse_line(line, 0, 0, &pattern_pool);
+            let metadata = parsed_line.metadata.unwrap();
+            (
+                metadata.episode,
+                metadata.episode_end,
+                parsed_line.diagnostic_list.len(),
+            )
+        })
+        .collect();
+        assert_eq!(
+            episode_list,
+            vec![
+                (Some(3), Some(5), 0),
+                (Some(3), Some(5), 0),
+                (Some(7), None, 0),
+                (Some(2), Some(4), 0),
+                (None, None, 1),
+                (None, None, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_season_part() {
+        let pattern_pool = PatternPool::new(
+            vec![
+                String::from(
+                    r"^(?<name>\S+)第(?<season>\S+)季(?<part>[上下])\s第(?<episode>\d+)集$",
+                ),
+                String::from(r"^(?<name>\S+)\sSeason\s(?<season>\d+)\s(?<part>\S+)\scour$"),
+            ],
+            vec![],
+        )
+        .unwrap();
+        let part_list: Vec<(Option<u16>, Option<u16>)> =
+            ["ABC第二季下 第3集", "ABC Season 2 2nd cour"]
+                .iter()
+                .map(|line| {
+                    let metadata = parse_line(line, 0, 0, &pattern_pool).metadata.unwrap();
+                    (metadata.season, metadata.part)
+                })
+                .collect();
+        assert_eq!(part_list, vec![(Some(2), Some(2)), (Some(2), Some(2))]);
+    }
+
+    #[test]
+    fn test_parse_extra_fields() {
+        let pattern_pool = PatternPool::new(
+            vec![String::from(
+                r"^(?<name>\S+)\s第(?<episode>\d+)集(?:\s@(?<platform>\S+))?(?:\swith\s(?<with>.+))?$",
+            )],
+            vec![],
+        )
+        .unwrap();
+        let metadata = parse_line("ABC 第3集 @Netflix with Alice", 0, 0, &pattern_pool)
+            .metadata
+            .unwrap();
+        assert_eq!(
+            metadata.extra_field_map,
+            BTreeMap::from([
+                (String::from("platform"), String::from("Netflix")),
+                (String::from("with"), String::from("Alice")),
+            ])
+        );
+
+        let metadata = parse_line("ABC 第4集", 0, 0, &pattern_pool)
+            .metadata
+            .unwrap();
+        assert!(metadata.extra_field_map.is_empty());
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let pattern_pool = PatternPool::new(
+            vec![String::from(r"^(?<name>\S+)(?:\s(?<note>.+))?$")],
+            vec![String::from(r"\s看完$")],
+        )
+        .unwrap()
+        .with_status_reg_pattern_list(
+            vec![String::from(r"\s弃$")],
+            vec![String::from(r"\s搁置$")],
+            vec![String::from(r"\s想看$")],
+        )
+        .unwrap();
+        let status_list: Vec<WatchStatus> = ["ABC 看完", "ABC 弃", "ABC 搁置", "ABC 想看", "ABC"]
+            .iter()
+            .map(|line| {
+                parse_line(line, 0, 0, &pattern_pool)
+                    .metadata
+                    .unwrap()
+                    .status
+            })
+            .collect();
+        assert_eq!(
+            status_list,
+            vec![
+                WatchStatus::Finished,
+                WatchStatus::Dropped,
+                WatchStatus::OnHold,
+                WatchStatus::PlanToWatch,
+                WatchStatus::Watching,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_metadata_relative_logged_time() {
+        let pattern_pool = PatternPool::new(
+            vec![String::from(r"^(?<name>\S+)\s(?<logged_time>.+)$")],
+            vec![],
+        )
+        .unwrap();
+        let mut parser = Parser::new(pattern_pool, None, 2, 1);
+        parser.file_modified_time =
+            NaiveDateTime::parse_from_str("2024-03-06 23:00", "%Y-%m-%d %H:%M").ok();
+        let lines: Vec<String> = ["A 昨天 21:00", "B 2024-03-08 20:00", "C 22:00", "D 某天"]
+            .map(String::from)
+            .to_vec();
+
+        let parse_result = parser.parse_metadata(&lines);
+        let logged_time_list: Vec<(Option<String>, bool)> = parse_result
+            .metadata_list
+            .iter()
+            .map(|m| {
+                (
+                    m.logged_time
+                        .map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
+                    m.b_logged_time_inferred,
+                )
+            })
+            .collect();
+        assert_eq!(
+            logged_time_list,
+            vec![
+                (Some(String::from("2024-03-05 21:00")), true),
+                (Some(String::from("2024-03-08 20:00")), false),
+                (Some(String::from("2024-03-08 22:00")), true),
+                (None, false),
+            ]
+        );
+        assert_eq!(parse_result.diagnostic_list.len(), 1);
+        assert_eq!(parse_result.diagnostic_list[0].provenance.line_number, 4);
+    }
+
+    #[test]
+    fn test_parse_metadata_date_header() {
+        let pattern_pool = PatternPool::new(
+            vec![
+                String::from(r"^(?<name>\S+)\s(?<logged_time>\d{1,2}:\d{2})$"),
+                String::from(r"^(?<name>\S+)$"),
+            ],
+            vec![],
+        )
+        .unwrap()
+        .with_date_header_pattern_list(vec![String::from(r"^\d{4}-\d{2}-\d{2}$")])
+        .unwrap();
+        let mut parser = Parser::new(pattern_pool, None, 2, 1);
+        let lines: Vec<String> = ["A", "2024-03-02", "B", "C 21:30", "2024-03-04", "D"]
+            .map(String::from)
+            .to_vec();
+
+        let parse_result = parser.parse_metadata(&lines);
+        assert!(parse_result.diagnostic_list.is_empty());
+        let logged_time_list: Vec<(&str, Option<String>)> = parse_result
+            .metadata_list
+            .iter()
+            .map(|m| {
+                (
+                    m.name.as_str(),
+                    m.logged_time
+                        .map(|t| t.format("%Y-%m-%d %H:%M").to_string()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            logged_time_list,
+            vec![
+                ("A", None),
+                ("B", Some(String::from("2024-03-02 00:00"))),
+                ("C", Some(String::from("2024-03-02 21:30"))),
+                ("D", Some(String::from("2024-03-04 00:00"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_metadata_diagnostics() {
+        let pattern_pool = PatternPool::new(
+            vec![
+                String::from(r"^(?<name>.+)\s第(?<episode>\S+)集$"),
+                String::from(r"^(?<season>\S+)\s(?<other>.+)$"),
+            ],
+            vec![],
+        )
+        .unwrap();
+        let mut parser = Parser::new(pattern_pool, None, 2, 1);
+        let lines: Vec<String> = ["ABC 第2集", "ABC 第x集", "no_space_at_all", "1 no name"]
+            .map(String::from)
+            .to_vec();
+
+        let parse_result = parser.parse_metadata(&lines);
+        assert_eq!(parse_result.metadata_list.len(), 2);
+        let reason_list: Vec<(usize, DiagnosticReason)> = parse_result
+            .diagnostic_list
+            .into_iter()
+            .map(|d| (d.provenance.line_number, d.reason))
+            .collect();
+        assert_eq!(
+            reason_list,
+            vec![
+                (
+                    2,
+                    DiagnosticReason::InvalidNumber {
+                        group: String::from("episode"),
+                        text: String::from("x"),
+                    }
+                ),
+                (3, DiagnosticReason::NoMatch),
+                (
+                    4,
+                    DiagnosticReason::BadCapture {
+                        group: String::from("name"),
+                    }
+                ),
+            ]
+        );
     }
 }
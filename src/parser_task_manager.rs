@@ -1,8 +1,8 @@
-use super::datatype::Metadata;
+use super::datatype::ParsedLine;
+use super::pattern::PatternPool;
 use std::any::Any;
 use std::fmt::Display;
 use std::num::NonZeroUsize;
-use std::sync::Arc;
 use thiserror::Error;
 
 #[allow(dead_code)]
@@ -42,16 +42,12 @@ impl From<Box<dyn Any + Send>> for ParserTaskManagerError {
 pub type ParserTaskManagerResult<T> = Result<T, ParserTaskManagerError>;
 
 pub trait ParserCallback:
-    Fn(&str, u32, u64, &Vec<String>, &Vec<String>) -> Option<Metadata> + Send + Sync + 'static + Clone
+    Fn(&str, usize, u64, &PatternPool) -> ParsedLine + Send + Sync + 'static + Clone
 {
 }
 
 impl<F> ParserCallback for F where
-    F: Fn(&str, u32, u64, &Vec<String>, &Vec<String>) -> Option<Metadata>
-        + Send
-        + Sync
-        + 'static
-        + Clone
+    F: Fn(&str, usize, u64, &PatternPool) -> ParsedLine + Send + Sync + 'static + Clone
 {
 }
 
@@ -60,11 +56,10 @@ pub struct ParserTask<F>
 where
     F: ParserCallback,
 {
-    pub index: u32,
+    // 1-based line number in the watching log.
+    pub line_number: usize,
     pub hash_value: u64,
     pub line: String,
-    pub reg_pattern_list: Vec<String>,
-    pub finished_reg_pattern_list: Vec<String>,
     pub callback: F,
 }
 
@@ -102,51 +97,112 @@ where
         self.task_list.push(task);
     }
 
+    // The line numbers of the added tasks, in the same order as the results of run.
+    pub fn line_number_list(&self) -> Vec<usize> {
+        self.task_list.iter().map(|task| task.line_number).collect()
+    }
+
     pub fn get_task_count(&self) -> usize {
         self.task_list.len()
     }
 
     pub fn get_thread_count(&self) -> usize {
         let total_tasks = self.get_task_count();
-        let calculated_threads = total_tasks.div_ceil(self.min_tasks_per_thread);
+        let calculated_threads = total_tasks.div_ceil(std::cmp::max(self.min_tasks_per_thread, 1));
         std::cmp::min(calculated_threads, self.max_thread_num)
     }
 
-    pub fn run(&self) -> ParserTaskManagerResult<Vec<Option<Metadata>>> {
-        let mut final_result = vec![];
-
+    // Run all the added tasks and drain the task list. The tasks are split into even chunks, one
+    // chunk per thread, and the results are returned in the same order as the tasks were added.
+    pub fn run(&mut self, pattern_pool: &PatternPool) -> ParserTaskManagerResult<Vec<ParsedLine>> {
         let thread_num = self.get_thread_count();
-        let task_num_per_thread = self.get_task_count();
-
-        let task_list_arc = Arc::new(self.task_list.clone());
-        let mut join_hanfle_list = vec![];
-
-        for i in 0..thread_num {
-            let tasks_arc = Arc::clone(&task_list_arc);
-            let join_handle = std::thread::spawn(move || {
-                let mut result = vec![];
-                let start_index = i * task_num_per_thread;
-                let end_index = std::cmp::min(start_index + task_num_per_thread, tasks_arc.len());
-                for j in start_index..end_index {
-                    let task = &tasks_arc[j];
-                    let metadata = (task.callback)(
-                        &task.line,
-                        task.index,
-                        task.hash_value,
-                        &task.reg_pattern_list,
-                        &task.finished_reg_pattern_list,
-                    );
-                    result.push(metadata);
-                }
-                result
-            });
-            join_hanfle_list.push(join_handle);
+        let task_list = std::mem::take(&mut self.task_list);
+        let mut final_result = Vec::with_capacity(task_list.len());
+
+        if thread_num == 0 {
+            return Ok(final_result);
         }
+        let task_num_per_thread = task_list.len().div_ceil(thread_num);
+
+        std::thread::scope(|scope| {
+            let join_handle_list: Vec<_> = task_list
+                .chunks(task_num_per_thread)
+                .map(|tasks| {
+                    scope.spawn(move || {
+                        tasks
+                            .iter()
+                            .map(|task| {
+                                (task.callback)(
+                                    &task.line,
+                                    task.line_number,
+                                    task.hash_value,
+                                    pattern_pool,
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            for join_handle in join_handle_list {
+                final_result.extend(join_handle.join()?);
+            }
+            Ok(final_result)
+        })
+    }
+}
 
-        for join_handle in join_hanfle_list {
-            final_result.extend(join_handle.join()?);
+#[cfg(test)]
+mod parser_task_manager_tests {
+    use super::super::datatype::{Metadata, Provenance};
+    use super::*;
+
+    type TestCallback = fn(&str, usize, u64, &PatternPool) -> ParsedLine;
+
+    fn echo(line: &str, line_number: usize, hash_value: u64, _: &PatternPool) -> ParsedLine {
+        let metadata = Metadata {
+            provenance: Provenance {
+                source_path: None,
+                line_number,
+            },
+            id: hash_value,
+            name: line.to_string(),
+            raw_line: line.to_string(),
+            ..Default::default()
+        };
+        ParsedLine {
+            metadata: Some(metadata),
+            date_header: None,
+            diagnostic_list: vec![],
         }
+    }
 
-        Ok(final_result)
+    #[test]
+    fn test_run_splits_tasks_and_keeps_order() {
+        let pattern_pool = PatternPool::new(vec![], vec![]).unwrap();
+        let mut task_manager = ParserTaskManager::<TestCallback>::new(4, 10);
+        for line_number in 1..=95 {
+            task_manager.add_task(ParserTask {
+                line_number,
+                hash_value: line_number as u64,
+                line: line_number.to_string(),
+                callback: echo,
+            });
+        }
+        // 95 tasks with at least 10 tasks per thread need 10 threads, capped by max_thread_num.
+        let thread_count = task_manager.get_thread_count();
+        assert!((1..=4).contains(&thread_count));
+
+        let result_list = task_manager.run(&pattern_pool).unwrap();
+        let line_number_list: Vec<usize> = result_list
+            .iter()
+            .filter_map(|parsed_line| parsed_line.metadata.as_ref())
+            .map(|m| m.provenance.line_number)
+            .collect();
+        assert_eq!(line_number_list, (1..=95).collect::<Vec<usize>>());
+
+        // The task list is drained after running.
+        assert_eq!(task_manager.get_task_count(), 0);
+        assert!(task_manager.run(&pattern_pool).unwrap().is_empty());
     }
 }
@@ -0,0 +1,262 @@
+use super::datatype::WatchStatus;
+use super::notation::RatingFormat;
+use regex::{Regex, RegexSet};
+use thiserror::Error;
+use xxhash_rust::xxh3;
+
+// Bump this whenever the parsing logic changes the produced Metadata for the same
+// pattern pool, so the cached entries parsed by the older logic are not reused.
+const PARSER_VERSION: u32 = 10;
+
+// The named groups which map to the Metadata fields, any other named group is kept as an
+// extra field.
+pub const METADATA_GROUP_LIST: &[&str] = &[
+    "name",
+    "season",
+    "part",
+    "kind",
+    "episode",
+    "episode_start",
+    "episode_end",
+    "time_at_episode",
+    "logged_time",
+    "note",
+    "rating",
+];
+
+#[derive(Error, Debug)]
+pub enum PatternError {
+    #[error("Invalid regex pattern in {list}[{index}]: {source}")]
+    InvalidPattern {
+        list: &'static str,
+        index: usize,
+        #[source]
+        source: regex::Error,
+    },
+}
+
+pub type PatternResult<T> = Result<T, PatternError>;
+
+// A list of compiled regex patterns, the RegexSet is used to find the first matching pattern
+// in one pass, the regex list is used to extract the captures of the matched one.
+pub struct CompiledPatternList {
+    pub pattern_list: Vec<String>,
+    regex_list: Vec<Regex>,
+    regex_set: RegexSet,
+}
+
+impl CompiledPatternList {
+    pub fn new(list: &'static str, pattern_list: Vec<String>) -> PatternResult<Self> {
+        let mut regex_list = Vec::with_capacity(pattern_list.len());
+        for (index, pattern) in pattern_list.iter().enumerate() {
+            let regex = Regex::new(pattern).map_err(|source| PatternError::InvalidPattern {
+                list,
+                index,
+                source,
+            })?;
+            regex_list.push(regex);
+        }
+        // Every pattern is already compiled successfully, so the set cannot fail on syntax.
+        let regex_set =
+            RegexSet::new(&pattern_list).map_err(|source| PatternError::InvalidPattern {
+                list,
+                index: 0,
+                source,
+            })?;
+        Ok(CompiledPatternList {
+            pattern_list,
+            regex_list,
+            regex_set,
+        })
+    }
+
+    // Return the index and the regex of the first pattern matching the line.
+    pub fn first_match(&self, line: &str) -> Option<(usize, &Regex)> {
+        self.regex_set
+            .matches(line)
+            .iter()
+            .next()
+            .map(|index| (index, &self.regex_list[index]))
+    }
+
+    pub fn regex(&self, index: usize) -> &Regex {
+        &self.regex_list[index]
+    }
+
+    // Return the indices of all the patterns matching the line, in the pattern order.
+    pub fn match_index_list(&self, line: &str) -> Vec<usize> {
+        self.regex_set.matches(line).into_iter().collect()
+    }
+}
+
+// All the compiled patterns used for parsing, it is created once per Parser and shared across
+// the worker threads.
+pub struct PatternPool {
+    pub reg_pattern_list: CompiledPatternList,
+    pub finished_reg_pattern_list: CompiledPatternList,
+    pub dropped_reg_pattern_list: CompiledPatternList,
+    pub on_hold_reg_pattern_list: CompiledPatternList,
+    pub plan_to_watch_reg_pattern_list: CompiledPatternList,
+    // The lines matching these patterns are date headers, they set the date for the following
+    // entries without a logged_time.
+    pub date_header_pattern_list: CompiledPatternList,
+    // The chrono formats tried in order before the built-in ones for parsing logged_time.
+    pub logged_time_format_list: Vec<String>,
+    pub rating_format: RatingFormat,
+    hash_value: u64,
+}
+
+impl PatternPool {
+    pub fn new(
+        reg_pattern_list: Vec<String>,
+        finished_reg_pattern_list: Vec<String>,
+    ) -> PatternResult<Self> {
+        let mut pattern_pool = PatternPool {
+            reg_pattern_list: CompiledPatternList::new("reg_pattern_list", reg_pattern_list)?,
+            finished_reg_pattern_list: CompiledPatternList::new(
+                "finished_reg_pattern_list",
+                finished_reg_pattern_list,
+            )?,
+            dropped_reg_pattern_list: CompiledPatternList::new("dropped_reg_pattern_list", vec![])?,
+            on_hold_reg_pattern_list: CompiledPatternList::new("on_hold_reg_pattern_list", vec![])?,
+            plan_to_watch_reg_pattern_list: CompiledPatternList::new(
+                "plan_to_watch_reg_pattern_list",
+                vec![],
+            )?,
+            date_header_pattern_list: CompiledPatternList::new("date_header_pattern_list", vec![])?,
+            logged_time_format_list: Vec::new(),
+            rating_format: RatingFormat::default(),
+            hash_value: 0,
+        };
+        pattern_pool.update_hash_value();
+        Ok(pattern_pool)
+    }
+
+    pub fn with_status_reg_pattern_list(
+        mut self,
+        dropped_reg_pattern_list: Vec<String>,
+        on_hold_reg_pattern_list: Vec<String>,
+        plan_to_watch_reg_pattern_list: Vec<String>,
+    ) -> PatternResult<Self> {
+        self.dropped_reg_pattern_list =
+            CompiledPatternList::new("dropped_reg_pattern_list", dropped_reg_pattern_list)?;
+        self.on_hold_reg_pattern_list =
+            CompiledPatternList::new("on_hold_reg_pattern_list", on_hold_reg_pattern_list)?;
+        self.plan_to_watch_reg_pattern_list = CompiledPatternList::new(
+            "plan_to_watch_reg_pattern_list",
+            plan_to_watch_reg_pattern_list,
+        )?;
+        self.update_hash_value();
+        Ok(self)
+    }
+
+    // The status pattern lists with their list names, the first matching list sets the status.
+    pub fn status_reg_pattern_list(
+        &self,
+    ) -> [(WatchStatus, &'static str, &CompiledPatternList); 4] {
+        [
+            (
+                WatchStatus::Finished,
+                "finished_reg_pattern_list",
+                &self.finished_reg_pattern_list,
+            ),
+            (
+                WatchStatus::Dropped,
+                "dropped_reg_pattern_list",
+                &self.dropped_reg_pattern_list,
+            ),
+            (
+                WatchStatus::OnHold,
+                "on_hold_reg_pattern_list",
+                &self.on_hold_reg_pattern_list,
+            ),
+            (
+                WatchStatus::PlanToWatch,
+                "plan_to_watch_reg_pattern_list",
+                &self.plan_to_watch_reg_pattern_list,
+            ),
+        ]
+    }
+
+    pub fn with_date_header_pattern_list(
+        mut self,
+        date_header_pattern_list: Vec<String>,
+    ) -> PatternResult<Self> {
+        self.date_header_pattern_list =
+            CompiledPatternList::new("date_header_pattern_list", date_header_pattern_list)?;
+        self.update_hash_value();
+        Ok(self)
+    }
+
+    pub fn with_logged_time_format_list(mut self, logged_time_format_list: Vec<String>) -> Self {
+        self.logged_time_format_list = logged_time_format_list;
+        self.update_hash_value();
+        self
+    }
+
+    pub fn with_rating_format(mut self, rating_format: RatingFormat) -> Self {
+        self.rating_format = rating_format;
+        self.update_hash_value();
+        self
+    }
+
+    // Everything affecting the parsing result is part of the pool's hash value.
+    fn update_hash_value(&mut self) {
+        let reg_pool_string = [
+            PARSER_VERSION.to_string(),
+            self.reg_pattern_list.pattern_list.join("###"),
+            self.finished_reg_pattern_list.pattern_list.join("###"),
+            self.dropped_reg_pattern_list.pattern_list.join("###"),
+            self.on_hold_reg_pattern_list.pattern_list.join("###"),
+            self.plan_to_watch_reg_pattern_list.pattern_list.join("###"),
+            self.date_header_pattern_list.pattern_list.join("###"),
+            self.logged_time_format_list.join("###"),
+            format!(
+                "{}###{}",
+                self.rating_format.star_max, self.rating_format.number_scale
+            ),
+        ]
+        .join("######");
+        self.hash_value = xxh3::xxh3_64(reg_pool_string.as_bytes());
+    }
+
+    // The caching key of a line combines the line's hash and the whole pattern pool's hash.
+    pub fn line_hash(&self, line: &str) -> u64 {
+        xxh3::xxh3_64_with_seed(line.as_bytes(), self.hash_value)
+    }
+}
+
+#[cfg(test)]
+mod pattern_tests {
+    use super::*;
+
+    #[test]
+    fn test_first_match_prefers_earlier_pattern() {
+        let pool = PatternPool::new(
+            vec![
+                String::from(r"(?<name>.+)\s看完$"),
+                String::from(r"(?<name>.+)$"),
+            ],
+            vec![],
+        )
+        .unwrap();
+
+        let (index, _) = pool.reg_pattern_list.first_match("ABC 看完").unwrap();
+        assert_eq!(index, 0);
+        let (index, _) = pool.reg_pattern_list.first_match("ABC").unwrap();
+        assert_eq!(index, 1);
+        assert!(pool.finished_reg_pattern_list.first_match("ABC").is_none());
+    }
+
+    #[test]
+    fn test_invalid_pattern_reports_index() {
+        let result = PatternPool::new(vec![String::from("ok"), String::from("(bad")], vec![]);
+        match result {
+            Err(PatternError::InvalidPattern { list, index, .. }) => {
+                assert_eq!(list, "reg_pattern_list");
+                assert_eq!(index, 1);
+            }
+            Ok(_) => panic!("The invalid pattern should be rejected"),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use super::datatype::WatchStatus;
+use super::parser::parse_line;
+use super::pattern::PatternPool;
+use super::stats::{Stats, StatsInfo};
+use chrono::NaiveDateTime;
+use std::fmt::Display;
+
+pub const DEFAULT_STALE_DAYS: u32 = 30;
+pub const DEFAULT_ABANDONED_DAYS: u32 = 180;
+
+// An unfinished watching not logged for a while.
+pub struct StaleWatching {
+    pub statsinfo: StatsInfo,
+    pub last_logged_time: NaiveDateTime,
+    pub idle_days: i64,
+    // Untouched for more than the abandoned threshold, otherwise only stale.
+    pub b_abandoned: bool,
+}
+
+impl Display for StaleWatching {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} - {}, untouched for {} days since {}",
+            self.statsinfo.title(),
+            if self.b_abandoned {
+                "abandoned"
+            } else {
+                "stale"
+            },
+            self.idle_days,
+            self.last_logged_time.date()
+        )
+    }
+}
+
+// The unfinished watching untouched for more than stale_days, the longest idle first. The
+// watching without any logged_time is skipped, as its age is unknown.
+pub fn find_stale(
+    stats: &Stats,
+    now: NaiveDateTime,
+    stale_days: u32,
+    abandoned_days: u32,
+) -> Vec<StaleWatching> {
+    let mut stale_list: Vec<StaleWatching> = stats
+        .stats_unfinished()
+        .into_iter()
+        .filter_map(|statsinfo| {
+            let last_logged_time = statsinfo
+                .related_entry
+                .iter()
+                .filter_map(|entry| entry.logged_time)
+                .max()?;
+            let idle_days = (now - last_logged_time).num_days();
+            (idle_days > stale_days as i64).then_some(StaleWatching {
+                statsinfo,
+                last_logged_time,
+                idle_days,
+                b_abandoned: idle_days > abandoned_days as i64,
+            })
+        })
+        .collect();
+    stale_list.sort_by_key(|stale| std::cmp::Reverse(stale.idle_days));
+    stale_list
+}
+
+// Fill the dropped entry template, such as `{name}第{season}季 弃 {date}`.
+pub fn render_dropped_line(template: &str, statsinfo: &StatsInfo, now: NaiveDateTime) -> String {
+    template
+        .replace("{name}", &statsinfo.name)
+        .replace(
+            "{season}",
+            &statsinfo
+                .season
+                .map(|season| season.to_string())
+                .unwrap_or_default(),
+        )
+        .replace(
+            "{part}",
+            &statsinfo
+                .part
+                .map(|part| part.to_string())
+                .unwrap_or_default(),
+        )
+        .replace("{datetime}", &now.format("%Y-%m-%d %H:%M").to_string())
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+}
+
+// The appended line must be parsed back as a dropped entry of the same watching, including the
+// part of a split season unless the parts are merged.
+pub fn check_dropped_line(
+    line: &str,
+    pattern_pool: &PatternPool,
+    statsinfo: &StatsInfo,
+    b_merge_parts: bool,
+) -> Result<(), String> {
+    let parsed_line = parse_line(line, 1, 0, pattern_pool);
+    if let Some(diagnostic) = parsed_line.diagnostic_list.first() {
+        return Err(diagnostic.to_string());
+    }
+    let Some(metadata) = parsed_line.metadata else {
+        return Err(String::from("the line is parsed as a date header"));
+    };
+    if metadata.status != WatchStatus::Dropped {
+        return Err(format!("the line is parsed as {}", metadata.status));
+    }
+    let part = if b_merge_parts { None } else { metadata.part };
+    if metadata.name != statsinfo.name
+        || metadata.season != statsinfo.season
+        || part != statsinfo.part
+    {
+        return Err(format!(
+            "the line is parsed as another watching `{}`",
+            metadata.name
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod stale_tests {
+    use super::super::parser::Parser;
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn pattern_pool() -> PatternPool {
+        PatternPool::new(
+            vec![String::from(
+                r"^(?<name>\S+)\s第(?<season>\d+)季(?<part>[上下])?(?:\s第(?<episode>\d+)集)?(?:\s弃)?\s(?<logged_time>\d{4}-\d{2}-\d{2})$",
+            )],
+            vec![],
+        )
+        .unwrap()
+        .with_status_reg_pattern_list(vec![String::from(r"\s弃\s")], vec![], vec![])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_find_stale_and_drop() {
+        let lines = [
+            "ABC 第1季 第1集 2023-01-01",
+            "DEF 第1季 第1集 2023-09-01",
+            "GHI 第1季 第1集 2023-10-20",
+        ]
+        .map(String::from);
+        let parse_result = Parser::new(pattern_pool(), None, 1, 1).parse_metadata(&lines);
+        let stats = Stats::new(parse_result.metadata_list, false);
+        let now = NaiveDate::from_ymd_opt(2023, 11, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+
+        let stale_list = find_stale(&stats, now, 30, 180);
+        let name_list: Vec<(&str, bool)> = stale_list
+            .iter()
+            .map(|stale| (stale.statsinfo.name.as_str(), stale.b_abandoned))
+            .collect();
+        assert_eq!(name_list, vec![("ABC", true), ("DEF", false)]);
+
+        let statsinfo = &stale_list[0].statsinfo;
+        let line = render_dropped_line("{name} 第{season}季 弃 {date}", statsinfo, now);
+        assert_eq!(line, "ABC 第1季 弃 2023-11-01");
+        assert!(check_dropped_line(&line, &pattern_pool(), statsinfo, false).is_ok());
+        let line = render_dropped_line("{name} 第{season}季 {date}", statsinfo, now);
+        assert!(check_dropped_line(&line, &pattern_pool(), statsinfo, false).is_err());
+    }
+
+    #[test]
+    fn test_dropped_line_keeps_part() {
+        let lines = [String::from("ABC 第2季下 第1集 2023-01-01")];
+        let now = NaiveDate::from_ymd_opt(2023, 11, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        for b_merge_parts in [false, true] {
+            let parse_result = Parser::new(pattern_pool(), None, 1, 1).parse_metadata(&lines);
+            let stats = Stats::new(parse_result.metadata_list, b_merge_parts);
+            let statsinfo = &stats.stats_all()[0];
+            let check = |template: &str| {
+                let line = render_dropped_line(template, statsinfo, now);
+                check_dropped_line(&line, &pattern_pool(), statsinfo, b_merge_parts).is_ok()
+            };
+            assert_eq!(check("{name} 第{season}季 弃 {date}"), b_merge_parts);
+            assert!(check("{name} 第{season}季下 弃 {date}"));
+        }
+    }
+}
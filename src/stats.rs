@@ -1,16 +1,180 @@
-use std::collections::HashMap;
+use chrono::{NaiveDateTime, NaiveTime};
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::LazyLock;
 
-use super::datatype::Metadata;
+use super::datatype::{Metadata, WatchStatus};
+
+// The `{placeholder}` of an output template, compiled once for all the rendered rows.
+static PLACEHOLDER_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{([A-Za-z0-9_.]+)\}").unwrap());
 
 #[derive(Clone)]
 pub struct StatsInfo {
     pub name: String,
     pub season: Option<u16>,
+    // None if the season is not split, or the parts are merged.
+    pub part: Option<u16>,
+    // The number of the viewing cycles which are finished.
     pub watched_times: u16,
-    pub b_finished: bool,
+    // The status of the latest entry.
+    pub status: WatchStatus,
+    // The episodes of the current cycle, every episode of a range entry counts as watched, the
+    // specials are not counted.
+    pub watched_episode_set: BTreeSet<u16>,
+    // The specials, OVAs, movies, recaps and decimal episodes of the current cycle, such as `SP`
+    // or `episode 12.5`.
+    pub special_list: Vec<String>,
+    // The extra fields of all the entries, the later entries override the earlier ones.
+    pub extra_field_map: BTreeMap<String, String>,
+    // The ratings of the entries in the logged order, on the 0-10 scale.
+    pub rating_list: Vec<f32>,
+    // All the entries in the logged order.
     pub related_entry: Vec<Metadata>,
+    // The index in related_entry where each viewing cycle starts, the first watching entry
+    // after a finished cycle starts a rewatch.
+    pub cycle_start_list: Vec<usize>,
+    // Whether the current cycle has a finished entry, any other status may come after it.
+    pub b_cycle_finished: bool,
+}
+
+// Where to continue an unfinished watching.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResumePoint {
+    // The latest regular episode of the current cycle.
+    pub episode: Option<u16>,
+    // The time in the latest episode, None if the episode was watched to the end.
+    pub time_at_episode: Option<NaiveTime>,
+    // The episode after the latest one when it was completed.
+    pub next_episode: Option<u16>,
+    pub last_watched_time: Option<NaiveDateTime>,
+}
+
+impl StatsInfo {
+    // The name with the season and the part if there are.
+    pub fn title(&self) -> String {
+        let mut title = match self.season {
+            Some(season) => format!("{} season {}", self.name, season),
+            None => self.name.clone(),
+        };
+        if let Some(part) = self.part {
+            title += &format!(" part {}", part);
+        }
+        title
+    }
+
+    // The entries of each viewing cycle, the first one is the first watch.
+    pub fn cycle_list(&self) -> Vec<&[Metadata]> {
+        self.cycle_start_list
+            .iter()
+            .enumerate()
+            .map(|(index, start)| {
+                let end = self
+                    .cycle_start_list
+                    .get(index + 1)
+                    .copied()
+                    .unwrap_or(self.related_entry.len());
+                &self.related_entry[*start..end]
+            })
+            .collect()
+    }
+
+    pub fn b_rewatching(&self) -> bool {
+        self.cycle_start_list.len() > 1
+    }
+
+    // `first watch` or `rewatch N` for the current cycle.
+    pub fn cycle_label(&self) -> String {
+        match self.cycle_start_list.len() {
+            0 | 1 => String::from("first watch"),
+            cycle_count => format!("rewatch {}", cycle_count - 1),
+        }
+    }
+
+    // The latest progress of the current cycle, an entry with a time at the episode is an
+    // unfinished episode to resume, otherwise the next episode is suggested.
+    pub fn resume_point(&self) -> ResumePoint {
+        let current_cycle = self.cycle_list().pop().unwrap_or_default();
+        let latest_entry = current_cycle
+            .iter()
+            .rev()
+            .find(|entry| !entry.b_special() && entry.episode.is_some());
+        let (episode, time_at_episode) = match latest_entry {
+            Some(entry) => (
+                entry.episode_range().map(|range| *range.end()),
+                entry.time_at_episode,
+            ),
+            None => (None, None),
+        };
+        ResumePoint {
+            episode,
+            time_at_episode,
+            next_episode: episode
+                .filter(|_| time_at_episode.is_none())
+                .and_then(|episode| episode.checked_add(1)),
+            last_watched_time: current_cycle
+                .iter()
+                .filter_map(|entry| entry.logged_time)
+                .max(),
+        }
+    }
+
+    pub fn latest_rating(&self) -> Option<f32> {
+        self.rating_list.last().copied()
+    }
+
+    pub fn average_rating(&self) -> Option<f32> {
+        (!self.rating_list.is_empty())
+            .then(|| self.rating_list.iter().sum::<f32>() / self.rating_list.len() as f32)
+    }
+
+    // Match a `key=value` condition against the extra fields, the value is matched as a
+    // case-insensitive substring.
+    pub fn b_field_matched(&self, condition: &str) -> bool {
+        let (key, value) = condition.split_once('=').unwrap_or((condition, ""));
+        self.extra_field_map.get(key.trim()).is_some_and(|field| {
+            field
+                .to_lowercase()
+                .contains(value.trim().to_lowercase().as_str())
+        })
+    }
+
+    // Fill a template such as `{title}: {episode_count} on {field.platform}`, an unknown or
+    // missing placeholder becomes empty.
+    pub fn render(&self, template: &str) -> String {
+        PLACEHOLDER_RE
+            .replace_all(template, |caps: &Captures| match &caps[1] {
+                "title" => self.title(),
+                "name" => self.name.clone(),
+                "season" => self
+                    .season
+                    .map(|season| season.to_string())
+                    .unwrap_or_default(),
+                "part" => self.part.map(|part| part.to_string()).unwrap_or_default(),
+                "status" => self.status.to_string(),
+                "watched_times" => self.watched_times.to_string(),
+                "cycle" => self.cycle_label(),
+                "episode_count" => self.watched_episode_set.len().to_string(),
+                "special_count" => self.special_list.len().to_string(),
+                "rating" => self
+                    .latest_rating()
+                    .map(|rating| format!("{:.1}", rating))
+                    .unwrap_or_default(),
+                "average_rating" => self
+                    .average_rating()
+                    .map(|rating| format!("{:.1}", rating))
+                    .unwrap_or_default(),
+                placeholder => placeholder
+                    .strip_prefix("field.")
+                    .and_then(|key| self.extra_field_map.get(key))
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+            .into_owned()
+    }
 }
 
+#[allow(dead_code)]
 pub struct Stats {
     metadata_list: Vec<Metadata>,
     statsinfo_list: Vec<StatsInfo>,
@@ -18,29 +182,63 @@ pub struct Stats {
 }
 
 impl Stats {
-    pub fn new(mut metadata_list: Vec<Metadata>) -> Self {
-        metadata_list.sort_by(|a, b| a.index.cmp(&b.index));
+    // The parts of a split season are tracked separately unless b_merge_parts is set.
+    pub fn new(mut metadata_list: Vec<Metadata>, b_merge_parts: bool) -> Self {
+        metadata_list.sort_by(|a, b| a.provenance.cmp(&b.provenance));
 
         let mut statsinfo_list: Vec<StatsInfo> = Vec::new();
         let mut statsinfo_index_by_name: HashMap<String, usize> = HashMap::new();
 
         for metadata in &metadata_list {
-            let title = format! {"{}-{:?}", &metadata.name, &metadata.season};
+            let part = if b_merge_parts { None } else { metadata.part };
+            let title = format! {"{}-{:?}-{:?}", &metadata.name, &metadata.season, part};
             if let std::collections::hash_map::Entry::Vacant(e) =
                 statsinfo_index_by_name.entry(title.clone())
             {
                 statsinfo_list.push(StatsInfo {
                     name: metadata.name.clone(),
                     season: metadata.season,
+                    part,
                     watched_times: 0,
-                    b_finished: metadata.b_finished,
-                    related_entry: vec![metadata.clone()],
+                    status: metadata.status,
+                    watched_episode_set: BTreeSet::new(),
+                    special_list: vec![],
+                    extra_field_map: BTreeMap::new(),
+                    rating_list: vec![],
+                    related_entry: vec![],
+                    cycle_start_list: vec![0],
+                    b_cycle_finished: false,
                 });
                 e.insert(statsinfo_list.len() - 1);
+            }
+            let statsinfo = &mut statsinfo_list[statsinfo_index_by_name[&title]];
+            if statsinfo.b_cycle_finished && metadata.status == WatchStatus::Watching {
+                statsinfo
+                    .cycle_start_list
+                    .push(statsinfo.related_entry.len());
+                statsinfo.watched_episode_set.clear();
+                statsinfo.special_list.clear();
+                statsinfo.b_cycle_finished = false;
+            }
+            if metadata.status == WatchStatus::Finished && !statsinfo.b_cycle_finished {
+                statsinfo.watched_times += 1;
+                statsinfo.b_cycle_finished = true;
+            }
+            statsinfo.status = metadata.status;
+            statsinfo.related_entry.push(metadata.clone());
+            statsinfo
+                .extra_field_map
+                .extend(metadata.extra_field_map.clone());
+            statsinfo.rating_list.extend(metadata.rating);
+            if metadata.b_special() {
+                let special_label = metadata.special_label();
+                if !statsinfo.special_list.contains(&special_label) {
+                    statsinfo.special_list.push(special_label);
+                }
             } else {
-                let index: usize = statsinfo_index_by_name[&title];
-                let statsinfo = &mut statsinfo_list[index];
-                statsinfo.b_finished = metadata.b_finished || statsinfo.b_finished;
+                statsinfo
+                    .watched_episode_set
+                    .extend(metadata.episode_range().into_iter().flatten());
             }
         }
         Stats {
@@ -54,16 +252,51 @@ impl Stats {
         self.statsinfo_list.clone()
     }
 
+    // The watching which is still going on or paused, the dropped and planned ones are not.
     pub fn stats_unfinished(&self) -> Vec<StatsInfo> {
         let mut result: Vec<StatsInfo> = Vec::new();
         for statsinfo in &self.statsinfo_list {
-            if !statsinfo.b_finished {
+            if matches!(
+                statsinfo.status,
+                WatchStatus::Watching | WatchStatus::OnHold
+            ) {
                 result.push(statsinfo.clone());
             }
         }
         result
     }
 
+    // The watching finished at least once with a rating, the best rated first by the latest
+    // rating. A rewatch or a pause after finishing keeps it in the list.
+    pub fn stats_rated(&self) -> Vec<StatsInfo> {
+        let mut result: Vec<StatsInfo> = self
+            .statsinfo_list
+            .iter()
+            .filter(|statsinfo| statsinfo.watched_times > 0 && !statsinfo.rating_list.is_empty())
+            .cloned()
+            .collect();
+        result.sort_by(|a, b| {
+            let rating = |statsinfo: &StatsInfo| statsinfo.latest_rating().unwrap_or_default();
+            rating(b).total_cmp(&rating(a))
+        });
+        result
+    }
+
+    // The unfinished watching with their resume points, the most recently watched first.
+    pub fn stats_next(&self) -> Vec<(StatsInfo, ResumePoint)> {
+        let mut result: Vec<(StatsInfo, ResumePoint)> = self
+            .stats_unfinished()
+            .into_iter()
+            .map(|statsinfo| {
+                let resume_point = statsinfo.resume_point();
+                (statsinfo, resume_point)
+            })
+            .collect();
+        // The stable sort keeps the logged order for the ones without a logged_time.
+        result.sort_by_key(|(_, resume_point)| std::cmp::Reverse(resume_point.last_watched_time));
+        result
+    }
+
     pub fn query_by_name(&self, name: &str) -> Vec<StatsInfo> {
         let mut result = Vec::new();
         for statsinfo in &self.statsinfo_list {
@@ -78,3 +311,289 @@ impl Stats {
         result
     }
 }
+
+#[cfg(test)]
+mod stats_tests {
+    use super::super::datatype::EpisodeKind;
+    use super::*;
+
+    fn metadata(name: &str, episode: Option<u16>, episode_end: Option<u16>) -> Metadata {
+        metadata_with_part(name, episode, episode_end, None)
+    }
+
+    fn metadata_with_part(
+        name: &str,
+        episode: Option<u16>,
+        episode_end: Option<u16>,
+        part: Option<u16>,
+    ) -> Metadata {
+        Metadata {
+            name: name.to_string(),
+            episode,
+            episode_end,
+            season: Some(1),
+            part,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_episode_range_counts_every_episode() {
+        let stats = Stats::new(
+            vec![
+                metadata("ABC", Some(1), None),
+                metadata("ABC", Some(3), Some(5)),
+                metadata("ABC", Some(4), None),
+                metadata("DEF", None, None),
+            ],
+            false,
+        );
+
+        let statsinfo_list = stats.stats_all();
+        assert_eq!(
+            statsinfo_list[0].watched_episode_set,
+            BTreeSet::from([1, 3, 4, 5])
+        );
+        assert!(statsinfo_list[1].watched_episode_set.is_empty());
+    }
+
+    #[test]
+    fn test_split_season_parts() {
+        let metadata_list = vec![
+            metadata_with_part("ABC", Some(1), None, Some(1)),
+            metadata_with_part("ABC", Some(12), None, Some(1)),
+            metadata_with_part("ABC", Some(1), None, Some(2)),
+        ];
+
+        let statsinfo_list = Stats::new(metadata_list.clone(), false).stats_all();
+        assert_eq!(statsinfo_list.len(), 2);
+        assert_eq!(statsinfo_list[0].title(), "ABC season 1 part 1");
+        assert_eq!(statsinfo_list[1].title(), "ABC season 1 part 2");
+        assert_eq!(statsinfo_list[1].watched_episode_set, BTreeSet::from([1]));
+
+        let statsinfo_list = Stats::new(metadata_list, true).stats_all();
+        assert_eq!(statsinfo_list.len(), 1);
+        assert_eq!(statsinfo_list[0].title(), "ABC season 1");
+        assert_eq!(
+            statsinfo_list[0].watched_episode_set,
+            BTreeSet::from([1, 12])
+        );
+    }
+
+    #[test]
+    fn test_specials_counted_separately() {
+        let mut special = metadata("ABC", None, None);
+        special.kind = EpisodeKind::Special;
+        let mut ova = metadata("ABC", Some(2), None);
+        ova.kind = EpisodeKind::Ova;
+        let mut decimal = metadata("ABC", None, None);
+        decimal.episode_decimal = Some(12.5);
+        let stats = Stats::new(
+            vec![
+                metadata("ABC", Some(1), None),
+                special.clone(),
+                ova,
+                decimal,
+                special,
+            ],
+            false,
+        );
+
+        let statsinfo_list = stats.stats_all();
+        assert_eq!(statsinfo_list.len(), 1);
+        assert_eq!(statsinfo_list[0].watched_episode_set, BTreeSet::from([1]));
+        assert_eq!(
+            statsinfo_list[0].special_list,
+            vec!["SP", "OVA 2", "episode 12.5"]
+        );
+    }
+
+    #[test]
+    fn test_extra_fields() {
+        let mut first = metadata("ABC", Some(1), None);
+        first
+            .extra_field_map
+            .insert(String::from("platform"), String::from("Netflix"));
+        first
+            .extra_field_map
+            .insert(String::from("with"), String::from("Alice"));
+        let mut second = metadata("ABC", Some(2), None);
+        second
+            .extra_field_map
+            .insert(String::from("platform"), String::from("Bilibili"));
+        let statsinfo_list = Stats::new(vec![first, second], false).stats_all();
+
+        let statsinfo = &statsinfo_list[0];
+        assert!(statsinfo.b_field_matched("platform=bili"));
+        assert!(!statsinfo.b_field_matched("platform=netflix"));
+        assert!(statsinfo.b_field_matched("with"));
+        assert!(!statsinfo.b_field_matched("language"));
+        assert_eq!(
+            statsinfo.render("{title} [{status}] {episode_count} on {field.platform} with {field.with}{field.language}"),
+            "ABC season 1 [watching] 2 on Bilibili with Alice"
+        );
+    }
+
+    #[test]
+    fn test_stats_rated() {
+        let rated = |name: &str, rating_list: &[f32], status: WatchStatus| {
+            rating_list
+                .iter()
+                .map(|rating| {
+                    let mut entry = metadata(name, None, None);
+                    entry.rating = Some(*rating);
+                    entry.status = status;
+                    entry
+                })
+                .collect::<Vec<Metadata>>()
+        };
+        let mut metadata_list = rated("ABC", &[6.0, 9.0], WatchStatus::Finished);
+        metadata_list.extend(rated("DEF", &[8.0], WatchStatus::Finished));
+        // DEF is rewatched after being rated.
+        metadata_list.push(metadata("DEF", Some(1), None));
+        metadata_list.extend(rated("GHI", &[10.0], WatchStatus::Watching));
+        metadata_list.push(metadata("JKL", Some(1), None));
+        let stats = Stats::new(metadata_list, false);
+
+        let statsinfo_list = stats.stats_rated();
+        let name_list: Vec<&str> = statsinfo_list.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(name_list, vec!["ABC", "DEF"]);
+        assert_eq!(statsinfo_list[0].latest_rating(), Some(9.0));
+        assert_eq!(statsinfo_list[0].average_rating(), Some(7.5));
+    }
+
+    #[test]
+    fn test_status_from_latest_entry() {
+        let entry = |name: &str, status: WatchStatus| {
+            let mut entry = metadata(name, None, None);
+            entry.status = status;
+            entry
+        };
+        let stats = Stats::new(
+            vec![
+                entry("ABC", WatchStatus::Watching),
+                entry("ABC", WatchStatus::Dropped),
+                entry("DEF", WatchStatus::Finished),
+                entry("DEF", WatchStatus::Watching),
+                entry("GHI", WatchStatus::OnHold),
+                entry("JKL", WatchStatus::PlanToWatch),
+            ],
+            false,
+        );
+
+        let status_list: Vec<WatchStatus> = stats
+            .stats_all()
+            .iter()
+            .map(|statsinfo| statsinfo.status)
+            .collect();
+        assert_eq!(
+            status_list,
+            vec![
+                WatchStatus::Dropped,
+                WatchStatus::Watching,
+                WatchStatus::OnHold,
+                WatchStatus::PlanToWatch,
+            ]
+        );
+        let name_list: Vec<String> = stats
+            .stats_unfinished()
+            .into_iter()
+            .map(|statsinfo| statsinfo.name)
+            .collect();
+        assert_eq!(name_list, vec!["DEF", "GHI"]);
+    }
+
+    #[test]
+    fn test_rewatch_cycles() {
+        let entry = |episode: Option<u16>, status: WatchStatus| {
+            let mut entry = metadata("ABC", episode, None);
+            entry.status = status;
+            entry
+        };
+        let mut metadata_list = vec![
+            entry(Some(1), WatchStatus::Watching),
+            entry(Some(2), WatchStatus::Watching),
+            entry(None, WatchStatus::Finished),
+            entry(None, WatchStatus::Finished),
+            entry(Some(1), WatchStatus::Watching),
+        ];
+        let statsinfo = &Stats::new(metadata_list.clone(), false).stats_all()[0];
+        assert_eq!(statsinfo.watched_times, 1);
+        assert!(statsinfo.b_rewatching());
+        assert_eq!(statsinfo.cycle_label(), "rewatch 1");
+        let cycle_length_list: Vec<usize> = statsinfo
+            .cycle_list()
+            .iter()
+            .map(|cycle| cycle.len())
+            .collect();
+        assert_eq!(cycle_length_list, vec![4, 1]);
+        assert_eq!(statsinfo.watched_episode_set, BTreeSet::from([1]));
+
+        metadata_list.push(entry(None, WatchStatus::Finished));
+        let statsinfo = &Stats::new(metadata_list, false).stats_all()[0];
+        assert_eq!(statsinfo.watched_times, 2);
+        assert_eq!(statsinfo.related_entry.len(), 6);
+    }
+
+    #[test]
+    fn test_rewatch_after_other_status() {
+        let entry = |episode: Option<u16>, status: WatchStatus| {
+            let mut entry = metadata("ABC", episode, None);
+            entry.status = status;
+            entry
+        };
+        for status_between in [WatchStatus::PlanToWatch, WatchStatus::OnHold] {
+            let metadata_list = vec![
+                entry(Some(1), WatchStatus::Watching),
+                entry(Some(2), WatchStatus::Watching),
+                entry(None, WatchStatus::Finished),
+                entry(None, status_between),
+                entry(Some(1), WatchStatus::Watching),
+            ];
+            let statsinfo = &Stats::new(metadata_list, false).stats_all()[0];
+            assert_eq!(statsinfo.watched_times, 1);
+            assert_eq!(statsinfo.cycle_start_list, vec![0, 4]);
+            assert!(statsinfo.b_rewatching());
+            assert_eq!(statsinfo.cycle_label(), "rewatch 1");
+            assert_eq!(statsinfo.watched_episode_set, BTreeSet::from([1]));
+        }
+    }
+
+    #[test]
+    fn test_stats_next() {
+        let entry = |name: &str, episode: u16, time_at_episode: Option<NaiveTime>, day: u32| {
+            let mut entry = metadata(name, Some(episode), None);
+            entry.time_at_episode = time_at_episode;
+            entry.logged_time = chrono::NaiveDate::from_ymd_opt(2023, 10, day)
+                .unwrap()
+                .and_hms_opt(21, 0, 0);
+            entry
+        };
+        let stats = Stats::new(
+            vec![
+                entry("ABC", 3, None, 1),
+                entry("ABC", 4, NaiveTime::from_hms_opt(0, 12, 30), 3),
+                entry("DEF", 5, None, 2),
+                entry("DEF", 6, None, 4),
+            ],
+            false,
+        );
+
+        let next_list = stats.stats_next();
+        assert_eq!(next_list[0].0.name, "DEF");
+        assert_eq!(next_list[0].1.episode, Some(6));
+        assert_eq!(next_list[0].1.next_episode, Some(7));
+        assert_eq!(next_list[1].0.name, "ABC");
+        assert_eq!(
+            next_list[1].1,
+            ResumePoint {
+                episode: Some(4),
+                time_at_episode: NaiveTime::from_hms_opt(0, 12, 30),
+                next_episode: None,
+                last_watched_time: chrono::NaiveDate::from_ymd_opt(2023, 10, 3)
+                    .unwrap()
+                    .and_hms_opt(21, 0, 0),
+            }
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use super::datatype::{Metadata, Provenance, WatchStatus};
+use super::parser::format_time_at_episode;
+use super::stats::Stats;
+use chrono::NaiveDateTime;
+use std::fmt::Display;
+
+pub const DEFAULT_GAP_DAYS: u32 = 14;
+
+pub enum TimelineEvent {
+    Entry { title: String, entry: Box<Metadata> },
+    // The first entry of a rewatch cycle, such as the 2nd watch of a title.
+    RewatchStarted { title: String, rewatch: usize },
+    // No entry is logged for more than the gap threshold between two sessions.
+    Gap { days: i64 },
+}
+
+impl Display for TimelineEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimelineEvent::Entry { title, entry } => {
+                match entry.logged_time {
+                    Some(logged_time) => write!(f, "{}", logged_time.format("%Y-%m-%d %H:%M"))?,
+                    None => write!(f, "{:<16}", "unknown time")?,
+                }
+                write!(f, "  {}", title)?;
+                if entry.b_special() {
+                    write!(f, ", {}", entry.special_label())?;
+                } else if let Some(episode_label) = entry.episode_label() {
+                    write!(f, ", episode {}", episode_label)?;
+                }
+                if let Some(time_at_episode) = entry.time_at_episode {
+                    write!(f, " at {}", format_time_at_episode(time_at_episode))?;
+                }
+                if entry.status != WatchStatus::Watching {
+                    write!(f, " [{}]", entry.status)?;
+                }
+                if let Some(rating) = entry.rating {
+                    write!(f, " rated {:.1}/10", rating)?;
+                }
+                if entry.occurrence > 0 {
+                    write!(f, " (logged {} times)", entry.occurrence + 1)?;
+                }
+                if entry.b_logged_time_inferred {
+                    match &entry.logged_time_text {
+                        Some(text) => write!(f, " (time inferred from `{}`)", text.trim())?,
+                        None => write!(f, " (time inferred from the date header)")?,
+                    }
+                }
+                if let Some(note) = &entry.note {
+                    write!(f, " ({})", note.trim())?;
+                }
+                Ok(())
+            }
+            TimelineEvent::RewatchStarted { title, rewatch } => {
+                write!(f, "                  {} rewatch {} starts", title, rewatch)
+            }
+            TimelineEvent::Gap { days } => {
+                write!(
+                    f,
+                    "                  ... {} days without watching ...",
+                    days
+                )
+            }
+        }
+    }
+}
+
+// All the entries of the titles matching the name, across the seasons and the parts, in the
+// logged order with the rewatch events and the gaps longer than gap_days between them.
+pub fn build_timeline(stats: &Stats, name: &str, gap_days: u32) -> Vec<TimelineEvent> {
+    // Each event with the position and the logged_time of its entry.
+    let mut entry_list: Vec<(Provenance, Option<NaiveDateTime>, TimelineEvent)> = Vec::new();
+    for statsinfo in stats.query_by_name(name) {
+        let title = statsinfo.title();
+        for (position, entry) in statsinfo.related_entry.iter().enumerate() {
+            if let Some(rewatch) = statsinfo
+                .cycle_start_list
+                .iter()
+                .position(|start| *start == position)
+                .filter(|cycle| *cycle > 0)
+            {
+                entry_list.push((
+                    entry.provenance.clone(),
+                    entry.logged_time,
+                    TimelineEvent::RewatchStarted {
+                        title: title.clone(),
+                        rewatch,
+                    },
+                ));
+            }
+            entry_list.push((
+                entry.provenance.clone(),
+                entry.logged_time,
+                TimelineEvent::Entry {
+                    title: title.clone(),
+                    entry: Box::new(entry.clone()),
+                },
+            ));
+        }
+    }
+    // The log is written in order, so the logged order is the chronological one. The sort is
+    // stable, the rewatch event stays before its entry.
+    entry_list.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+
+    let mut timeline = Vec::with_capacity(entry_list.len());
+    let mut previous_logged_time: Option<NaiveDateTime> = None;
+    // The gap goes before the rewatch event, which shares the logged_time with its entry.
+    for (_, logged_time, event) in entry_list {
+        if let Some(logged_time) = logged_time {
+            if let Some(previous_logged_time) = previous_logged_time {
+                let days = (logged_time - previous_logged_time).num_days();
+                if days > gap_days as i64 {
+                    timeline.push(TimelineEvent::Gap { days });
+                }
+            }
+            previous_logged_time = Some(logged_time);
+        }
+        timeline.push(event);
+    }
+    timeline
+}
+
+#[cfg(test)]
+mod timeline_tests {
+    use super::super::parser::Parser;
+    use super::super::pattern::PatternPool;
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_build_timeline() {
+        let pattern_pool = PatternPool::new(
+            vec![String::from(
+                r"^(?<name>\S+)\s第(?<season>\d+)季(?:\s第(?<episode>\d+)集)?(?:\s(?<time_at_episode>\d{1,2}:\d{2}))?(?:\s看完)?\s(?<logged_time>\d{4}-\d{2}-\d{2})$",
+            )],
+            vec![String::from(r"\s看完\s")],
+        )
+        .unwrap();
+        let lines = [
+            "ABC 第1季 第1集 2023-01-01",
+            "DEF 第1季 第1集 2023-01-02",
+            "ABC 第1季 第2集 12:30 2023-01-03",
+            "ABC 第1季 看完 2023-01-04",
+            "ABC 第2季 第1集 2023-03-01",
+            "ABC 第1季 第1集 2023-05-02",
+        ]
+        .map(String::from);
+        let parse_result = Parser::new(pattern_pool, None, 1, 1).parse_metadata(&lines);
+        let stats = Stats::new(parse_result.metadata_list, false);
+
+        let timeline: Vec<String> = build_timeline(&stats, "abc", DEFAULT_GAP_DAYS)
+            .iter()
+            .map(|event| event.to_string())
+            .collect();
+        assert_eq!(
+            timeline,
+            vec![
+                "2023-01-01 00:00  ABC season 1, episode 1",
+                "2023-01-03 00:00  ABC season 1, episode 2 at 12:30",
+                "2023-01-04 00:00  ABC season 1 [finished]",
+                "                  ... 56 days without watching ...",
+                "2023-03-01 00:00  ABC season 2, episode 1",
+                "                  ... 62 days without watching ...",
+                "                  ABC season 1 rewatch 1 starts",
+                "2023-05-02 00:00  ABC season 1, episode 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inferred_time_marked() {
+        let entry = Metadata {
+            name: String::from("ABC"),
+            episode: Some(2),
+            logged_time: NaiveDate::from_ymd_opt(2024, 3, 6)
+                .unwrap()
+                .and_hms_opt(21, 0, 0),
+            logged_time_text: Some(String::from("昨天 21:00")),
+            b_logged_time_inferred: true,
+            ..Default::default()
+        };
+        let event = TimelineEvent::Entry {
+            title: String::from("ABC"),
+            entry: Box::new(entry),
+        };
+        assert_eq!(
+            event.to_string(),
+            "2024-03-06 21:00  ABC, episode 2 (time inferred from `昨天 21:00`)"
+        );
+    }
+}